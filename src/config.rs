@@ -0,0 +1,165 @@
+use dune_cli::types::EngineSize;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Defaults read from the config file. Command-line flags take precedence over them.
+///
+/// The settings at the top of the file are overridden by those of the `[default]` section,
+/// themselves overridden by those of the `[profiles.<name>]` section selected with `--profile`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub engine_size: Option<EngineSize>,
+    /// Seconds between execution status checks.
+    pub poll_interval: Option<u64>,
+    /// Delimiter of the CSV output, a single ASCII character (`\t` for tabs).
+    pub csv_delimiter: Option<String>,
+    /// Directory where relative output paths are written.
+    pub output_dir: Option<PathBuf>,
+    /// API key, used unless one is given on the command line or in the environment.
+    pub api_key: Option<String>,
+    /// Base URL of the API, e.g. of a proxy, instead of the public endpoint.
+    pub base_url: Option<String>,
+}
+
+impl Config {
+    // Settings of `self`, falling back to those of `base` for the unset ones.
+    fn or(self, base: Config) -> Config {
+        Config {
+            engine_size: self.engine_size.or(base.engine_size),
+            poll_interval: self.poll_interval.or(base.poll_interval),
+            csv_delimiter: self.csv_delimiter.or(base.csv_delimiter),
+            output_dir: self.output_dir.or(base.output_dir),
+            api_key: self.api_key.or(base.api_key),
+            base_url: self.base_url.or(base.base_url),
+        }
+    }
+}
+
+// TOML file in the user's config directory, e.g. `~/.config/dune-cli/config.toml`.
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("dune-cli").join("config.toml"))
+}
+
+/// Loads the config from the given path, which must exist, or from the default path, if any,
+/// with the settings of the given profile, which must exist as well.
+pub fn load_config(path: Option<&Path>, profile: Option<&str>) -> io::Result<Config> {
+    let contents = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => match config_path().map(fs::read_to_string) {
+            Some(Ok(contents)) => contents,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => String::new(),
+        },
+    };
+    parse_config(&contents, profile)
+}
+
+fn parse_config(contents: &str, profile: Option<&str>) -> io::Result<Config> {
+    let mut table: toml::Table = toml::from_str(contents).map_err(invalid_data)?;
+    // the sections are taken out first, so that unknown top-level settings are still rejected
+    let default = table.remove("default");
+    let profiles = table.remove("profiles");
+
+    let mut config: Config = table.try_into().map_err(invalid_data)?;
+    if let Some(default) = default {
+        let default: Config = default.try_into().map_err(invalid_data)?;
+        config = default.or(config);
+    }
+    // all the profiles are parsed, so that a mistake in one of them doesn't go unnoticed
+    let mut profiles: BTreeMap<String, Config> = match profiles {
+        Some(profiles) => profiles.try_into().map_err(invalid_data)?,
+        None => BTreeMap::new(),
+    };
+    if let Some(name) = profile {
+        let Some(profile) = profiles.remove(name) else {
+            let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "no profile {} in the config file (available profiles: {})",
+                    name,
+                    match available.is_empty() {
+                        true => "none".to_string(),
+                        false => available.join(", "),
+                    }
+                ),
+            ));
+        };
+        config = profile.or(config);
+    }
+    Ok(config)
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config(
+            r#"
+            engine_size = "large"
+            poll_interval = 10
+            csv_delimiter = ","
+            output_dir = "outputs"
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(config.engine_size, Some(EngineSize::Large));
+        assert_eq!(config.poll_interval, Some(10));
+        assert_eq!(config.csv_delimiter.as_deref(), Some(","));
+        assert_eq!(config.output_dir, Some(PathBuf::from("outputs")));
+
+        let config = parse_config("", None).unwrap();
+        assert!(config.engine_size.is_none());
+
+        assert!(parse_config("engine_size = \"huge\"", None).is_err());
+        assert!(parse_config("poll_intervall = 10", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_config_profiles() {
+        let contents = r#"
+            poll_interval = 10
+
+            [default]
+            api_key = "personal-key"
+            engine_size = "medium"
+
+            [profiles.team]
+            api_key = "team-key"
+            base_url = "https://dune-proxy.example.com/api"
+        "#;
+
+        let config = parse_config(contents, None).unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("personal-key"));
+        assert_eq!(config.engine_size, Some(EngineSize::Medium));
+        assert_eq!(config.poll_interval, Some(10));
+        assert!(config.base_url.is_none());
+
+        // the profile's settings override those of `[default]`, which fill in the others
+        let config = parse_config(contents, Some("team")).unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("team-key"));
+        assert_eq!(
+            config.base_url.as_deref(),
+            Some("https://dune-proxy.example.com/api")
+        );
+        assert_eq!(config.engine_size, Some(EngineSize::Medium));
+        assert_eq!(config.poll_interval, Some(10));
+
+        let e = parse_config(contents, Some("prod")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().contains("available profiles: team"));
+        assert!(parse_config("[profiles.team]\napi_kye = \"key\"", None).is_err());
+    }
+}
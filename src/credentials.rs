@@ -0,0 +1,101 @@
+use std::{fs, io, path::Path};
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "dune-cli";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "api-key";
+
+/// Resolves the Dune API key.
+///
+/// Precedence: `--api-key` flag > `--api-key-file` flag > `DUNE_API_KEY` env variable (or `.env`
+/// file) > `DUNE_API_KEY_FILE` env variable > config file (of the selected profile) > OS keyring.
+/// The keyring is only checked when the `keyring` feature is enabled.
+pub fn resolve_api_key(
+    api_key: Option<String>,
+    api_key_file: Option<&Path>,
+    config_api_key: Option<String>,
+) -> io::Result<Option<String>> {
+    if api_key.is_some() {
+        return Ok(api_key);
+    }
+    if let Some(path) = api_key_file {
+        return read_api_key_file(path).map(Some);
+    }
+    if let Ok(api_key) = std::env::var("DUNE_API_KEY") {
+        return Ok(Some(api_key));
+    }
+    if let Ok(path) = std::env::var("DUNE_API_KEY_FILE") {
+        return read_api_key_file(Path::new(&path)).map(Some);
+    }
+    if config_api_key.is_some() {
+        return Ok(config_api_key);
+    }
+
+    #[cfg(feature = "keyring")]
+    return Ok(get_keyring_api_key());
+    #[cfg(not(feature = "keyring"))]
+    Ok(None)
+}
+
+/// Reads the API key from the file, without the trailing whitespace and newlines.
+fn read_api_key_file(path: &Path) -> io::Result<String> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not read the API key file {}: {}", path.display(), e),
+        )
+    })?;
+    let api_key = contents.trim_end();
+    if api_key.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the API key file {} is empty", path.display()),
+        ));
+    }
+    Ok(api_key.to_string())
+}
+
+/// Reads the API key stored in the OS keyring, if any.
+#[cfg(feature = "keyring")]
+fn get_keyring_api_key() -> Option<String> {
+    match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).and_then(|entry| entry.get_password())
+    {
+        Ok(api_key) => Some(api_key),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            tracing::debug!("Could not read the API key from the OS keyring: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Prompts for the API key (without echoing it) and stores it in the OS keyring.
+#[cfg(feature = "keyring")]
+pub fn prompt_and_store_api_key() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = rpassword::prompt_password("Dune API key: ")?;
+    let api_key = api_key.trim();
+    if api_key.is_empty() {
+        return Err("the API key cannot be empty".into());
+    }
+
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.set_password(api_key)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_api_key_file() {
+        let path = std::env::temp_dir().join(format!("dune-cli-api-key-{}", std::process::id()));
+        fs::write(&path, "secret-key \n").unwrap();
+        assert_eq!(read_api_key_file(&path).unwrap(), "secret-key");
+
+        fs::write(&path, "\n").unwrap();
+        assert!(read_api_key_file(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_api_key_file(&path).is_err());
+    }
+}
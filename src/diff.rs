@@ -0,0 +1,178 @@
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+
+// Value of the cells missing from a row.
+static NULL: JsonValue = JsonValue::Null;
+
+/// Differences between the rows of two executions, joined on a key column.
+#[derive(Debug)]
+pub struct RowsDiff<'a> {
+    /// Rows only in the right results, in their order.
+    pub added: Vec<&'a JsonValue>,
+    /// Rows only in the left results, in their order.
+    pub removed: Vec<&'a JsonValue>,
+    /// Rows in both results whose cells differ, in the order of the left results.
+    pub changed: Vec<ChangedRow<'a>>,
+    pub unchanged: usize,
+}
+
+#[derive(Debug)]
+pub struct ChangedRow<'a> {
+    pub key: &'a JsonValue,
+    pub columns: Vec<ChangedColumn<'a>>,
+}
+
+#[derive(Debug)]
+pub struct ChangedColumn<'a> {
+    pub name: &'a str,
+    pub left: &'a JsonValue,
+    pub right: &'a JsonValue,
+}
+
+/// Joins the rows on the `key` column and compares the cells of the given columns.
+///
+/// Fails if a row has no value for the key, or if the key isn't unique, as the rows to
+/// compare would then be ambiguous.
+pub fn diff_rows<'a>(
+    left: &'a [JsonValue],
+    right: &'a [JsonValue],
+    key: &str,
+    columns: &'a [String],
+) -> Result<RowsDiff<'a>, String> {
+    let left_index = index_rows(left, key, "left")?;
+    let right_index = index_rows(right, key, "right")?;
+    let left_keys: HashSet<&str> = left_index.iter().map(|(k, _)| k.as_str()).collect();
+    let right_rows: HashMap<&str, &JsonValue> = right_index
+        .iter()
+        .map(|(k, row)| (k.as_str(), *row))
+        .collect();
+
+    let mut diff = RowsDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+        unchanged: 0,
+    };
+    for (row_key, left_row) in &left_index {
+        let Some(right_row) = right_rows.get(row_key.as_str()) else {
+            diff.removed.push(left_row);
+            continue;
+        };
+        let changed: Vec<ChangedColumn> = columns
+            .iter()
+            .filter_map(|name| {
+                let left = left_row.get(name).unwrap_or(&NULL);
+                let right = right_row.get(name).unwrap_or(&NULL);
+                (left != right).then_some(ChangedColumn { name, left, right })
+            })
+            .collect();
+        match changed.is_empty() {
+            true => diff.unchanged += 1,
+            false => diff.changed.push(ChangedRow {
+                key: &left_row[key],
+                columns: changed,
+            }),
+        }
+    }
+    diff.added = right_index
+        .iter()
+        .filter(|(k, _)| !left_keys.contains(k.as_str()))
+        .map(|(_, row)| *row)
+        .collect();
+    Ok(diff)
+}
+
+// Pairs each row with its key, as JSON so that e.g. `1` and `"1"` are different keys, in the
+// order of the rows.
+fn index_rows<'a>(
+    rows: &'a [JsonValue],
+    key: &str,
+    side: &str,
+) -> Result<Vec<(String, &'a JsonValue)>, String> {
+    let mut seen = HashSet::new();
+    let mut index = Vec::with_capacity(rows.len());
+    for row in rows {
+        let value = match row.get(key) {
+            Some(value) if !value.is_null() => value.to_string(),
+            _ => {
+                return Err(format!(
+                    "a row of the {} results has no {}: {}",
+                    side, key, row
+                ))
+            }
+        };
+        if !seen.insert(value.clone()) {
+            return Err(format!(
+                "{} isn't unique in the {} results, e.g. {}",
+                key, side, value
+            ));
+        }
+        index.push((value, row));
+    }
+    Ok(index)
+}
+
+impl RowsDiff<'_> {
+    /// Formats the differences one row per line: `-` for the removed rows, `+` for the added
+    /// ones and `~` for the changed ones, with the cells that changed.
+    pub fn format(&self, key: &str) -> String {
+        let mut lines = String::new();
+        for row in &self.removed {
+            lines.push_str(&format!("- {}\n", row));
+        }
+        for row in &self.added {
+            lines.push_str(&format!("+ {}\n", row));
+        }
+        for row in &self.changed {
+            let columns: Vec<String> = row
+                .columns
+                .iter()
+                .map(|column| format!("{}: {} -> {}", column.name, column.left, column.right))
+                .collect();
+            lines.push_str(&format!("~ {}={}: {}\n", key, row.key, columns.join(", ")));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_rows() {
+        let left = vec![
+            json!({"pool": "a", "tvl": 10, "fee": 0.3}),
+            json!({"pool": "b", "tvl": 20, "fee": 0.3}),
+            json!({"pool": "c", "tvl": 30, "fee": 0.05}),
+        ];
+        let right = vec![
+            json!({"pool": "d", "tvl": 40, "fee": 0.3}),
+            json!({"pool": "c", "tvl": 35, "fee": 0.05}),
+            json!({"pool": "a", "tvl": 10, "fee": 0.3}),
+        ];
+        let columns = ["pool", "tvl", "fee"].map(String::from);
+        let diff = diff_rows(&left, &right, "pool", &columns).unwrap();
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(
+            diff.format("pool"),
+            "- {\"fee\":0.3,\"pool\":\"b\",\"tvl\":20}\n\
+             + {\"fee\":0.3,\"pool\":\"d\",\"tvl\":40}\n\
+             ~ pool=\"c\": tvl: 30 -> 35\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_rows_invalid_key() {
+        let columns = ["pool".to_string()];
+        let duplicated = vec![json!({"pool": "a"}), json!({"pool": "a"})];
+        assert!(diff_rows(&duplicated, &[], "pool", &columns).is_err());
+        let missing = vec![json!({"pool": null})];
+        assert!(diff_rows(&[], &missing, "pool", &columns).is_err());
+        // keys of different types don't match
+        let (left, right) = ([json!({"id": 1})], [json!({"id": "1"})]);
+        let diff = diff_rows(&left, &right, "id", &columns).unwrap();
+        assert_eq!((diff.added.len(), diff.removed.len()), (1, 1));
+    }
+}
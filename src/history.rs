@@ -0,0 +1,64 @@
+use dune_cli::types::EngineSize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Submitted execution, appended to the history file after every `execute_query`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub query_id: u64,
+    pub execution_id: String,
+    /// Submission time, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub engine_size: EngineSize,
+}
+
+impl HistoryEntry {
+    pub fn new(query_id: u64, execution_id: String, engine_size: EngineSize) -> Self {
+        Self {
+            query_id,
+            execution_id,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            engine_size,
+        }
+    }
+}
+
+// JSON-lines file in the user's config directory, e.g. `~/.config/dune-cli/history.jsonl`.
+fn history_path() -> io::Result<PathBuf> {
+    match dirs::config_dir() {
+        Some(dir) => Ok(dir.join("dune-cli").join("history.jsonl")),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no config directory for the current user",
+        )),
+    }
+}
+
+pub fn append_history(entry: &HistoryEntry) -> io::Result<()> {
+    let path = history_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+/// Loads the history, oldest first. Lines that can't be parsed are skipped.
+pub fn load_history() -> io::Result<Vec<HistoryEntry>> {
+    match fs::read_to_string(history_path()?) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
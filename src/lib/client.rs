@@ -1,309 +1,2401 @@
 #![allow(dead_code)]
+use super::pagination::PageCollector;
 use super::types::*;
 
+use chrono::Utc;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value as JsonValue;
-use tracing::{debug, error, info};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 #[derive(Debug)]
 pub enum DuneError {
-    RequestError,
-    ParseError,
-    EncodingError,
+    /// The request couldn't be sent, or its response couldn't be read.
+    RequestError(reqwest::Error),
+    /// The body of the response isn't the expected JSON.
+    ParseError {
+        source: serde_json::Error,
+        /// Start of the body, truncated to `MAX_BODY_PREFIX` bytes, if it was read.
+        body: Option<String>,
+    },
+    /// The query string of the request couldn't be encoded.
+    EncodingError(serde_urlencoded::ser::Error),
     QueryNotFinished,
-    QueryStatusError(ExecutionStatus),
+    /// The execution failed or was cancelled, with the reason reported by the API, if any.
+    QueryStatusError {
+        status: ExecutionStatus,
+        message: Option<String>,
+    },
+    /// HTTP 402: the account's plan or credits don't cover the request.
+    PaymentRequired(String),
+    /// The page of results exceeds the maximum datapoints per request.
+    DatapointLimitExceeded(String),
+    /// HTTP 404: the query, execution or resource doesn't exist.
+    NotFound(String),
+    /// The execution, identified by its id, didn't complete within the maximum wait time.
+    PollTimeout(String),
+    /// The operation didn't complete within the overall timeout.
+    Timeout(Duration),
+    /// The page of results at the given offset timed out on every attempt.
+    PageTimeout {
+        offset: u64,
+        attempts: u32,
+    },
+    /// The results of the execution have expired, so the query has to be executed again.
+    ResultsExpired {
+        execution_id: String,
+    },
+    /// Several saved queries have the given name, listed with their ids.
+    AmbiguousQueryName {
+        name: String,
+        candidates: Vec<QuerySummary>,
+    },
+    /// The query parameters aren't a JSON object.
+    InvalidParams(String),
+    /// Any other error response, with its HTTP status code and the message of the API's error
+    /// envelope (`{"error": "..."}`), or the raw body if it isn't one.
+    ApiError {
+        status: u16,
+        message: String,
+    },
+    /// The execution, identified by its id, returned no rows although some were required.
+    EmptyResults {
+        execution_id: String,
+    },
+    /// The request wasn't sent, as the client is in dry-run mode.
+    DryRun,
+    /// Writing the downloaded results failed.
+    WriteError(std::io::Error),
+    /// The request kept failing with HTTP 429, 5xx or connection errors after all the retries.
+    RetriesExhausted {
+        attempts: u32,
+        /// Status code of the last response, `None` if the request itself failed.
+        last_status: Option<u16>,
+        /// Why the last request failed, e.g. a DNS or TLS error, if it did.
+        last_error: Option<reqwest::Error>,
+    },
 }
 
+impl From<reqwest::Error> for DuneError {
+    fn from(e: reqwest::Error) -> Self {
+        DuneError::RequestError(e)
+    }
+}
+
+impl From<serde_json::Error> for DuneError {
+    fn from(e: serde_json::Error) -> Self {
+        DuneError::ParseError {
+            source: e,
+            body: None,
+        }
+    }
+}
+
+impl From<serde_urlencoded::ser::Error> for DuneError {
+    fn from(e: serde_urlencoded::ser::Error) -> Self {
+        DuneError::EncodingError(e)
+    }
+}
+
+/// Bytes of the body kept in a `ParseError`, enough to tell what the response was.
+const MAX_BODY_PREFIX: usize = 512;
+
+/// Smallest page size used when retrying pages that exceed the datapoints per request limit.
+/// Rows wide enough to exceed the limit even then won't fit in any page, so the error is
+/// returned rather than retrying with ever smaller pages.
+const MIN_PAGE_SIZE: u64 = 100;
+
+/// Seconds between execution status checks, unless specified otherwise.
+pub const DEFAULT_POLL_INTERVAL: u64 = 5;
+
+/// Retries of idempotent requests failing with HTTP 429, 5xx or connection errors, unless specified otherwise.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Queries per page when listing the saved queries.
+const LIST_QUERIES_PAGE_SIZE: u64 = 100;
+
+/// Executions submitted at once by `execute_many`, unless specified otherwise.
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+/// Delay before the first retry, doubled on each subsequent one, unless specified otherwise.
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound of the backoff delay between retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Header carrying the API key, which is never logged.
+const API_KEY_HEADER: &str = "X-Dune-API-Key";
+
+/// Public endpoint of the Dune API.
+pub const DEFAULT_BASE_URL: &str = "https://api.dune.com/api";
+
+/// Timeout of each request, from connecting until the response body is read, unless
+/// specified otherwise. Generous, as a page of results can weigh tens of megabytes.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `User-Agent` of the requests unless specified otherwise, identifying the traffic of this tool.
+pub const DEFAULT_USER_AGENT: &str = concat!("dune-cli/", env!("CARGO_PKG_VERSION"));
+
 pub struct DuneClient {
     api_key: String,
+    /// Shared by all the requests, so that connections are pooled.
+    http: reqwest::Client,
+    /// Timeout, user agent and headers the HTTP client is built with, kept to rebuild it when
+    /// one of them changes.
+    timeout: Duration,
+    user_agent: String,
+    headers: HeaderMap,
+    /// Base URL of the API, without a trailing slash.
+    base_url: String,
+    page_timeout: Option<Duration>,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    /// Whether to log the requests instead of sending them.
+    dry_run: bool,
 }
 
 impl DuneClient {
     pub fn new(api_key: String) -> Self {
-        Self { api_key }
+        Self::with_base_url(api_key, DEFAULT_BASE_URL)
+    }
+
+    /// Creates a client that sends the requests to the given base URL, e.g. a proxy of the
+    /// Dune API, instead of the public endpoint.
+    pub fn with_base_url(api_key: String, base_url: &str) -> Self {
+        Self::build(api_key, base_url, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Creates a client whose requests time out after the given duration.
+    pub fn with_timeout(api_key: String, timeout: Duration) -> Self {
+        Self::build(api_key, DEFAULT_BASE_URL, timeout)
+    }
+
+    fn build(api_key: String, base_url: &str, timeout: Duration) -> Self {
+        let user_agent = DEFAULT_USER_AGENT.to_string();
+        let headers = HeaderMap::new();
+        Self {
+            api_key,
+            http: http_client(timeout, &user_agent, &headers),
+            timeout,
+            user_agent,
+            headers,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            page_timeout: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            dry_run: false,
+        }
+    }
+
+    /// Sets a timeout for each page of paginated results.
+    /// A page that times out is retried, up to the maximum number of retries, rather than
+    /// aborting the whole download.
+    pub fn with_page_timeout(mut self, page_timeout: Duration) -> Self {
+        self.page_timeout = Some(page_timeout);
+        self
     }
 
+    /// Sets how many times idempotent requests are retried on HTTP 429, 5xx or connection errors.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which is doubled on each subsequent retry.
+    pub fn with_retry_base_delay(mut self, retry_base_delay: Duration) -> Self {
+        self.retry_base_delay = retry_base_delay;
+        self
+    }
+
+    /// Sets the `User-Agent` of the requests, instead of [`DEFAULT_USER_AGENT`].
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self.http = http_client(self.timeout, &self.user_agent, &self.headers);
+        self
+    }
+
+    /// Adds headers to every request, e.g. those required by a gateway in front of the API.
+    /// Unlike the API key, they aren't redacted, but they aren't logged with the requests either.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self.http = http_client(self.timeout, &self.user_agent, &self.headers);
+        self
+    }
+
+    /// Sets whether the requests are only logged (with the API key redacted) instead of sent,
+    /// in which case every request fails with [`DuneError::DryRun`].
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    /// Sends an idempotent request, retrying it with exponential backoff on HTTP 429, 5xx or
+    /// connection errors. The `Retry-After` header takes precedence over the backoff delay.
+    pub async fn send_with_retry(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, DuneError> {
+        self.check_dry_run(&request)?;
+        let mut attempt = 0;
+        loop {
+            let Some(attempt_request) = request.try_clone() else {
+                // streaming bodies can't be cloned, so they can't be retried either
+                return Ok(request.send().await?);
+            };
+
+            trace_request(&attempt_request);
+            let (last_status, last_error, retry_after) = match attempt_request.send().await {
+                Ok(res) if is_retryable_status(res.status()) => {
+                    (Some(res.status().as_u16()), None, retry_after(&res))
+                }
+                Ok(res) => return Ok(res),
+                Err(e) if is_retryable_error(&e) => {
+                    debug!("Request failed: {:?}", e);
+                    (None, Some(e), None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt >= self.max_retries {
+                return Err(DuneError::RetriesExhausted {
+                    attempts: attempt + 1,
+                    last_status,
+                    last_error,
+                });
+            }
+
+            let delay =
+                retry_after.unwrap_or_else(|| backoff_delay(self.retry_base_delay, attempt));
+            let reason = match (last_status, &last_error) {
+                (Some(status), _) => status.to_string(),
+                (None, Some(e)) => e.to_string(),
+                (None, None) => "unknown error".to_string(),
+            };
+            warn!("Request failed ({}). Retrying in {:?}...", reason, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends a request that isn't retried, e.g. because it isn't idempotent.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, DuneError> {
+        self.check_dry_run(&request)?;
+        trace_request(&request);
+        Ok(request.send().await?)
+    }
+
+    // In dry-run mode, logs the request and fails instead of sending it.
+    fn check_dry_run(&self, request: &reqwest::RequestBuilder) -> Result<(), DuneError> {
+        if !self.dry_run {
+            return Ok(());
+        }
+        if let Some(description) = describe_request(request) {
+            info!("Dry run: {}", description);
+        }
+        Err(DuneError::DryRun)
+    }
+
+    #[instrument(skip_all, fields(query_id = query_id))]
     pub async fn execute_query(
         &self,
         query_id: u64,
         performance: EngineSize,
         params: Option<JsonValue>,
     ) -> Result<ExecuteQueryResponse, DuneError> {
-        let client = reqwest::Client::new();
-        let request_builder = client
-            .post(format!(
-                "https://api.dune.com/api/v1/query/{}/execute",
-                query_id
-            ))
-            .header("X-Dune-API-Key", &self.api_key)
+        validate_params(params.as_ref())?;
+
+        let request_builder = self
+            .http
+            .post(self.url(&format!("v1/query/{}/execute", query_id)))
+            .header(API_KEY_HEADER, &self.api_key)
             .header("Content-Type", "application/json")
             .json(&ExecuteQueryParams {
                 performance,
                 params,
             });
 
-        // Build the request to inspect the body
-        let request = request_builder
-            .try_clone()
-            .expect("Failed to clone request")
-            .build()
-            .expect("Failed to build request");
-
-        // Log the request body
-        if let Some(body) = request.body() {
-            if let Ok(body_str) = String::from_utf8(body.as_bytes().unwrap().to_vec()) {
-                debug!("Request body: {}", body_str);
-            }
-        }
+        let response = self.send(request_builder).await?;
+        trace!("Response: {:#?}", response);
 
-        let response = match request_builder.send().await {
-            Ok(res) => {
-                debug!("Response: {:#?}", res);
-                res
-            }
-            Err(_) => return Err(DuneError::RequestError),
-        };
+        parse_response::<ExecuteQueryResponse>(response).await
+    }
 
-        response
-            .json::<ExecuteQueryResponse>()
-            .await
-            .map_err(|_| DuneError::ParseError)
+    /// Executes raw SQL, without a saved query. The execution is then handled as that of a
+    /// saved query, e.g. with [`DuneClient::wait_until_finished`] and
+    /// [`DuneClient::get_query_results`] on its execution id.
+    #[instrument(skip_all)]
+    pub async fn execute_sql(
+        &self,
+        sql: &str,
+        performance: EngineSize,
+    ) -> Result<ExecuteQueryResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .post(self.url("v1/sql/execute"))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&ExecuteSqlParams { sql, performance }),
+            )
+            .await?;
+        trace!("Response: {:#?}", response);
+
+        parse_response::<ExecuteQueryResponse>(response).await
+    }
+
+    /// Submits the executions concurrently, at most `concurrency` at a time so as not to hit
+    /// the rate limits. The responses are returned in the order of the queries.
+    pub async fn execute_many(
+        &self,
+        queries: Vec<(u64, EngineSize, Option<JsonValue>)>,
+        concurrency: usize,
+    ) -> Vec<Result<ExecuteQueryResponse, DuneError>> {
+        let mut responses: Vec<(usize, Result<ExecuteQueryResponse, DuneError>)> =
+            stream::iter(queries.into_iter().enumerate())
+                .map(|(i, (query_id, performance, params))| async move {
+                    (i, self.execute_query(query_id, performance, params).await)
+                })
+                .buffer_unordered(concurrency.max(1))
+                .collect()
+                .await;
+        responses.sort_by_key(|(i, _)| *i);
+        responses.into_iter().map(|(_, res)| res).collect()
     }
 
+    #[instrument(skip_all, fields(execution_id = execution_id))]
     pub async fn get_execution_status(
         &self,
         execution_id: &str,
     ) -> Result<ExecutionStatusResponse, DuneError> {
-        let response = match reqwest::Client::new()
-            .get(format!(
-                "https://api.dune.com/api/v1/execution/{}/status",
-                execution_id
-            ))
-            .header("X-Dune-API-Key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .send()
-            .await
-        {
-            Ok(res) => res,
-            Err(_) => return Err(DuneError::RequestError),
-        };
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url(&format!("v1/execution/{}/status", execution_id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json"),
+            )
+            .await?;
 
-        response
-            .json::<ExecutionStatusResponse>()
-            .await
-            .map_err(|_| DuneError::ParseError)
+        parse_response::<ExecutionStatusResponse>(response).await
+    }
+
+    /// Cancels an ongoing execution, returning its state after the cancellation.
+    #[instrument(skip_all, fields(execution_id = execution_id))]
+    pub async fn cancel_execution(
+        &self,
+        execution_id: &str,
+    ) -> Result<ExecuteQueryResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .post(self.url(&format!("v1/execution/{}/cancel", execution_id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json"),
+            )
+            .await?;
+
+        let cancel = parse_response::<CancelExecutionResponse>(response).await?;
+        debug!("Cancel response: {:?}", cancel);
+
+        // the cancel endpoint only acknowledges the request, so fetch the resulting state
+        let status = self.get_execution_status(execution_id).await?;
+        Ok(ExecuteQueryResponse {
+            execution_id: status.execution_id,
+            status: status.status,
+        })
+    }
+
+    /// Retrieves the metadata (name, description, tags, parameters, ...) of a saved query.
+    #[instrument(skip_all, fields(query_id = query_id))]
+    pub async fn get_query(&self, query_id: u64) -> Result<QueryMetadata, DuneError> {
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url(&format!("v1/query/{}", query_id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json"),
+            )
+            .await?;
+
+        parse_response::<QueryMetadata>(response).await
+    }
+
+    /// Saves a new query, returning its id.
+    #[instrument(skip_all, fields(query_name = name))]
+    pub async fn create_query(
+        &self,
+        name: &str,
+        sql: String,
+        parameters: Vec<QueryParameter>,
+        description: Option<String>,
+        is_private: bool,
+    ) -> Result<QueryCreateResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .post(self.url("v1/query"))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&CreateQueryParams {
+                        name: name.to_string(),
+                        query_sql: sql,
+                        parameters,
+                        description,
+                        is_private,
+                    }),
+            )
+            .await?;
+
+        parse_response::<QueryCreateResponse>(response).await
+    }
+
+    /// Updates the given fields of a saved query, keeping the others as they are.
+    #[instrument(skip_all, fields(query_id = query_id))]
+    pub async fn update_query(
+        &self,
+        query_id: u64,
+        changes: UpdateQueryParams,
+    ) -> Result<QueryCreateResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .patch(self.url(&format!("v1/query/{}", query_id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&changes),
+            )
+            .await?;
+
+        parse_response::<QueryCreateResponse>(response).await
+    }
+
+    /// Lists a page of the queries owned by the user (or team) of the API key.
+    pub async fn list_queries(
+        &self,
+        offset: u64,
+        limit: u64,
+    ) -> Result<ListQueriesResponse, DuneError> {
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url(&format!("v1/queries?limit={}&offset={}", limit, offset)))
+                    .header(API_KEY_HEADER, &self.api_key),
+            )
+            .await?;
+
+        parse_response::<ListQueriesResponse>(response).await
+    }
+
+    /// Resolves the id of a saved query from its name, paging through the queries of the user.
+    /// The name is matched exactly, falling back to a case-insensitive match.
+    /// Fails with [`DuneError::AmbiguousQueryName`] if several queries match.
+    #[instrument(skip_all, fields(query_name = name))]
+    pub async fn find_query_id_by_name(&self, name: &str) -> Result<u64, DuneError> {
+        let mut queries = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = self.list_queries(offset, LIST_QUERIES_PAGE_SIZE).await?;
+            let page_size = page.queries.len() as u64;
+            queries.extend(page.queries);
+            offset += page_size;
+            let is_last = match page.total {
+                Some(total) => offset >= total,
+                None => page_size < LIST_QUERIES_PAGE_SIZE,
+            };
+            if is_last || page_size == 0 {
+                break;
+            }
+        }
+        debug!("{} queries listed", queries.len());
+
+        let (exact, other): (Vec<QuerySummary>, Vec<QuerySummary>) =
+            queries.into_iter().partition(|query| query.name == name);
+        let mut candidates = match exact.is_empty() {
+            true => other
+                .into_iter()
+                .filter(|query| query.name.eq_ignore_ascii_case(name))
+                .collect(),
+            false => exact,
+        };
+        match candidates.len() {
+            0 => Err(DuneError::NotFound(format!("no query named '{}'", name))),
+            1 => Ok(candidates.remove(0).id),
+            _ => Err(DuneError::AmbiguousQueryName {
+                name: name.to_string(),
+                candidates,
+            }),
+        }
     }
 
     pub async fn get_materialized_view_results(
         &self,
         id: &str,
     ) -> Result<MaterializedViewResponse, DuneError> {
-        let response = match reqwest::Client::new()
-            .get(format!(
-                "https://api.dune.com/api/v1/materialized-views/{}",
-                id
-            ))
-            .header("X-Dune-API-Key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .send()
-            .await
-        {
-            Ok(res) => res,
-            Err(_) => return Err(DuneError::RequestError),
-        };
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url(&format!("v1/materialized-views/{}", id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json"),
+            )
+            .await?;
 
-        response
-            .json::<MaterializedViewResponse>()
-            .await
-            .map_err(|_| DuneError::ParseError)
+        parse_response::<MaterializedViewResponse>(response).await
+    }
+
+    /// Retrieves the credit and storage usage of the account owning the API key.
+    pub async fn get_account_info(&self) -> Result<AccountInfo, DuneError> {
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url("v1/usage"))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json"),
+            )
+            .await?;
+
+        parse_response::<AccountInfo>(response).await
+    }
+
+    /// Triggers a refresh of the materialized view, returning the execution that refreshes it.
+    #[instrument(skip_all, fields(materialized_view = id))]
+    pub async fn refresh_materialized_view(
+        &self,
+        id: &str,
+        performance: EngineSize,
+    ) -> Result<RefreshMaterializedViewResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .post(self.url(&format!("v1/materialized-views/{}/refresh", id)))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&RefreshMaterializedViewParams { performance }),
+            )
+            .await?;
+
+        parse_response::<RefreshMaterializedViewResponse>(response).await
+    }
+
+    /// Uploads the CSV data (comma-delimited, with a header row) as a new Dune table.
+    pub async fn upload_csv(
+        &self,
+        table_name: &str,
+        data: String,
+        description: Option<String>,
+        is_private: bool,
+    ) -> Result<UploadCsvResponse, DuneError> {
+        let response = self
+            .send(
+                self.http
+                    .post(self.url("v1/table/upload/csv"))
+                    .header(API_KEY_HEADER, &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&UploadCsvParams {
+                        table_name: table_name.to_string(),
+                        data,
+                        description,
+                        is_private,
+                    }),
+            )
+            .await?;
+
+        parse_response::<UploadCsvResponse>(response).await
     }
 
     pub async fn get_query_results(
         &self,
         id: &str,
         filters: QueryResultsFilter,
-        peak: bool,
+        options: ResultsOptions,
+        peak: Option<u64>,
     ) -> Result<QueryResult, DuneError> {
-        let mut rows: Vec<JsonValue> = Vec::new();
-        let limit = if peak { 10 } else { 1000 };
-        let (url_path, mut params) = match id.parse::<u64>() {
-            // if the id is a u64, it must be a query_id
-            Ok(query_id) => (
-                format!("v1/query/{}/results", query_id),
-                ResultsParams::new_query(query_id, false, 0, limit, None, filters),
-            ),
-            // otherwise, it is an execution_id
-            Err(_) => (
-                format!("v1/execution/{}/results", id),
-                ResultsParams::new_execution(id, false, 0, limit, None, filters),
-            ),
+        self.get_query_results_with_progress(id, filters, options, peak, |_, _| {})
+            .await
+    }
+
+    /// Same as [`DuneClient::get_query_results`], calling `on_progress` after each page with
+    /// the number of rows retrieved so far and the total number of rows of the results.
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn get_query_results_with_progress(
+        &self,
+        id: &str,
+        filters: QueryResultsFilter,
+        options: ResultsOptions,
+        peak: Option<u64>,
+        on_progress: impl Fn(u128, u128),
+    ) -> Result<QueryResult, DuneError> {
+        let mut pager = self.results_pager(id, filters, options, peak);
+        let mut pages = PageCollector::new();
+        let mut result = QueryResult::default();
+
+        while let Some((offset, response)) = pager.next_page().await? {
+            if pages.page_count() == 0 {
+                debug!("response metadata: {:?}", response.result.metadata);
+                result.query_id = response.query_id;
+                result.is_partial = response.is_partial();
+                result.execution_id = response.execution_id;
+                result.state = response.state;
+                result.metadata = response.result.metadata;
+            }
+            pages.insert(offset, response.result.rows);
+            on_progress(pager.rows_fetched(), pager.total_row_count());
+        }
+
+        result.rows = pages.into_rows();
+        result.metadata.datapoint_count = pager.datapoint_count();
+        result.page_count = pager.page_count();
+        result.next_offset = pager.resume_offset();
+        pager.log_summary();
+        Ok(result)
+    }
+
+    /// Same as [`DuneClient::get_query_results`], but once the first page has been fetched, the
+    /// offsets of the remaining pages are computed from its `total_row_count` and page size, and
+    /// the pages are fetched concurrently, at most `concurrency` at a time so as not to hit the
+    /// rate limits. The rows are reassembled in the order of their offsets.
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn get_query_results_parallel(
+        &self,
+        id: &str,
+        filters: QueryResultsFilter,
+        options: ResultsOptions,
+        concurrency: usize,
+    ) -> Result<QueryResult, DuneError> {
+        let mut pager = self.results_pager(id, filters.clone(), options.clone(), None);
+        let mut pages = PageCollector::new();
+        let mut result = QueryResult::default();
+
+        let (offset, response) = match pager.next_page().await? {
+            Some(page) => page,
+            None => return Ok(result),
         };
-        let mut params_encoded = match params.url_encode() {
-            Ok(str) => str,
-            Err(_) => return Err(DuneError::EncodingError),
+        debug!("response metadata: {:?}", response.result.metadata);
+        result.query_id = response.query_id;
+        result.is_partial = response.is_partial();
+        result.execution_id = response.execution_id;
+        result.state = response.state;
+        result.metadata = response.result.metadata;
+        pages.insert(offset, response.result.rows);
+        result.next_offset = pager.resume_offset();
+        result.metadata.datapoint_count = pager.datapoint_count();
+        result.page_count = pager.page_count();
+
+        let start = match pager.next_offset() {
+            Some(start) => start,
+            None => {
+                result.rows = pages.into_rows();
+                pager.log_summary();
+                return Ok(result);
+            }
         };
 
-        let response = match reqwest::Client::new()
-            .get(format!(
-                "https://api.dune.com/api/{}?{}",
-                &url_path, &params_encoded
-            ))
-            .header("X-Dune-API-Key", &self.api_key)
-            .send()
-            .await
-        {
-            Ok(res) => {
-                debug!("{:#?}", res);
-                res
+        // one page per range, at the page size settled by the first page
+        let page_size = pager.page_size();
+        let total_row_count = pager.total_row_count();
+        let max_end = options
+            .max_rows
+            .map(|max_rows| options.offset.saturating_add(max_rows));
+        let end = u64::try_from(total_row_count)
+            .unwrap_or(u64::MAX)
+            .min(max_end.unwrap_or(u64::MAX));
+        let mut offsets: Vec<u64> = (start..end).step_by(page_size as usize).collect();
+        if offsets.is_empty() {
+            offsets.push(start);
+        }
+        // the last range follows `next_offset` to the end, in case the total was underestimated
+        let last = offsets[offsets.len() - 1];
+        debug!(
+            "Fetching {} pages of {} rows, {} at a time",
+            offsets.len(),
+            page_size,
+            concurrency
+        );
+
+        let mut fetches = stream::iter(offsets)
+            .map(|offset| {
+                let max_rows = match offset == last {
+                    true => max_end.map(|max_end| max_end.saturating_sub(offset)),
+                    false => Some(page_size),
+                };
+                let options = ResultsOptions {
+                    offset,
+                    limit: Some(page_size),
+                    single_page: false,
+                    max_rows,
+                    ..options.clone()
+                };
+                let filters = filters.clone();
+                async move {
+                    let mut pager = self.results_pager(id, filters, options, None);
+                    pager.log_progress = false;
+                    let mut rows = Vec::new();
+                    while let Some((_, response)) = pager.next_page().await? {
+                        rows.extend(response.result.rows);
+                    }
+                    Ok::<_, DuneError>((
+                        offset,
+                        rows,
+                        pager.resume_offset(),
+                        pager.datapoint_count(),
+                        pager.page_count(),
+                    ))
+                }
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        let mut rows_fetched = u128::from(options.offset) + pages.row_count() as u128;
+        while let Some(fetched) = fetches.next().await {
+            let (offset, rows, resume_offset, datapoint_count, page_count) = fetched?;
+            if offset == last {
+                result.next_offset = resume_offset;
             }
-            Err(_) => return Err(DuneError::RequestError),
+            result.metadata.datapoint_count += datapoint_count;
+            result.page_count += page_count;
+            rows_fetched += rows.len() as u128;
+            pages.insert(offset, rows);
+            log_progress(rows_fetched, total_row_count);
+        }
+
+        result.rows = pages.into_rows();
+        log_fetch_summary(
+            result.rows.len() as u64,
+            result.page_count,
+            result.metadata.datapoint_count,
+        );
+        Ok(result)
+    }
+
+    /// Retrieves the latest result of the query without executing it, so that no credits are
+    /// spent on a new execution. With `params`, the latest execution with those parameters
+    /// is matched.
+    pub async fn get_latest_query_results(
+        &self,
+        query_id: u64,
+        params: Option<JsonValue>,
+    ) -> Result<QueryResult, DuneError> {
+        let options = ResultsOptions {
+            params,
+            ..Default::default()
         };
+        self.get_query_results(
+            &query_id.to_string(),
+            QueryResultsFilter::new(),
+            options,
+            None,
+        )
+        .await
+    }
 
-        let response = match response.json::<QueryResultsResponse>().await {
-            Ok(res) => {
-                debug!("{:#?}", res);
-                res
+    /// Streams the results to the writer as CSV, as rendered by Dune, instead of converting the
+    /// JSON results. The pages are followed until the last one, writing the header row only
+    /// once. Returns the number of bytes written.
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn get_query_results_csv<W: Write>(
+        &self,
+        id: &str,
+        writer: &mut W,
+    ) -> Result<u64, DuneError> {
+        let url_path = match id.parse::<ResultId>() {
+            Ok(ResultId::Query(query_id)) => format!("v1/query/{}/results/csv", query_id),
+            _ => format!("v1/execution/{}/results/csv", id),
+        };
+
+        let (mut offset, mut written) = (None, 0);
+        loop {
+            let url = match offset {
+                Some(offset) => format!("{}?offset={}", url_path, offset),
+                None => url_path.clone(),
+            };
+            let mut response = self
+                .send_with_retry(
+                    self.http
+                        .get(self.url(&url))
+                        .header(API_KEY_HEADER, &self.api_key),
+                )
+                .await?;
+            if !response.status().is_success() {
+                return Err(error_response(response).await);
             }
-            Err(_) => {
-                return Err(DuneError::ParseError);
+            let next_offset = response
+                .headers()
+                .get("x-dune-next-offset")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+
+            // every page starts with the header row, which is only kept for the first one
+            let mut skip_header = offset.is_some();
+            while let Some(chunk) = response.chunk().await? {
+                let mut chunk = &chunk[..];
+                if skip_header {
+                    match chunk.iter().position(|byte| *byte == b'\n') {
+                        Some(end) => {
+                            chunk = &chunk[end + 1..];
+                            skip_header = false;
+                        }
+                        None => continue,
+                    }
+                }
+                writer.write_all(chunk).map_err(DuneError::WriteError)?;
+                written += chunk.len() as u64;
             }
-        };
 
-        if !response.is_execution_finished {
-            return Err(DuneError::QueryNotFinished);
+            debug!("next_offset: {:?}", next_offset);
+            match next_offset {
+                Some(next_offset) => offset = Some(next_offset),
+                None => return Ok(written),
+            }
+        }
+    }
+
+    /// Returns a pager that fetches the pages of results one at a time, as they are requested.
+    /// If a `peak` count is set, only the first page is fetched, of that many records unless
+    /// a limit is given.
+    /// With `options.single_page`, only the first page is fetched as well, at the given limit.
+    pub fn results_pager<'a>(
+        &'a self,
+        id: &'a str,
+        filters: QueryResultsFilter,
+        options: ResultsOptions,
+        peak: Option<u64>,
+    ) -> ResultsPager<'a> {
+        let limit = options.limit.or(peak).unwrap_or(1000);
+        let offset = options.offset;
+        let columns = options.columns.clone();
+        let (url_path, mut params) = results_request(
+            id,
+            options.ignore_datapoint_limit,
+            offset,
+            limit,
+            options.columns,
+            filters,
+            options.sort_by,
+        );
+        if let Some(query_params) = &options.params {
+            params.set_query_parameters(query_params);
+        }
+        ResultsPager {
+            client: self,
+            id,
+            url_path,
+            params,
+            paginate: peak.is_none() && !options.single_page,
+            columns,
+            columns_checked: false,
+            next_offset: Some(offset),
+            resume_offset: Some(offset),
+            max_rows: options.max_rows,
+            rows_returned: 0,
+            rows_fetched: offset.into(),
+            total_row_count: 0,
+            datapoint_count: 0,
+            page_count: 0,
+            log_progress: true,
         }
+    }
 
-        let metadata = response.result.metadata;
-        debug!("response metadata: {:?}", metadata);
-        rows.extend(response.result.rows);
+    /// Streams the rows of the results, fetching each page lazily once the rows of the
+    /// previous one have been consumed, so that the whole result set is never held in memory.
+    pub fn get_query_results_stream<'a>(
+        &'a self,
+        id: &'a str,
+        filters: QueryResultsFilter,
+        options: ResultsOptions,
+        peak: Option<u64>,
+    ) -> impl Stream<Item = Result<JsonValue, DuneError>> + 'a {
+        stream::try_unfold(
+            self.results_pager(id, filters, options, peak),
+            |mut pager| async move {
+                Ok::<_, DuneError>(pager.next_page().await?.map(|(_, page)| {
+                    let rows = stream::iter(page.result.rows.into_iter().map(Ok));
+                    (rows, pager)
+                }))
+            },
+        )
+        .try_flatten()
+    }
 
-        if !peak {
-            let mut next_offset = response.next_offset;
-            debug!("next_offset: {:?}", next_offset);
-            while next_offset.is_some() {
-                debug!("{:?} records processed...", params.get_offset());
-                params.update_offset(next_offset.unwrap());
-                params_encoded = match params.url_encode() {
-                    Ok(str) => str,
-                    Err(_) => return Err(DuneError::ParseError),
-                };
+    /// Retrieves the metadata (column names, types and counts) of the results,
+    /// fetching a single row instead of the whole result set.
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn get_results_metadata(&self, id: &str) -> Result<QueryResultMetadata, DuneError> {
+        let (url_path, params) =
+            results_request(id, false, 0, 1, None, QueryResultsFilter::new(), None);
 
-                let response = match reqwest::Client::new()
-                    .get(format!(
-                        "https://api.dune.com/api/{}?{}",
-                        &url_path, &params_encoded
-                    ))
-                    .header("X-Dune-API-Key", &self.api_key)
-                    .send()
-                    .await
-                {
-                    Ok(res) => {
-                        debug!("{:#?}", res);
-                        res
-                    }
-                    Err(_) => return Err(DuneError::RequestError),
-                };
+        let response = self.get_results_page(&url_path, &params).await?;
+        check_results_state(&response)?;
 
-                let response = match response.json::<QueryResultsResponse>().await {
-                    Ok(res) => {
-                        debug!("{:#?}", res);
-                        res
-                    }
-                    Err(_) => {
-                        return Err(DuneError::ParseError);
-                    }
-                };
+        Ok(response.result.metadata)
+    }
+
+    async fn get_results_page(
+        &self,
+        url_path: &str,
+        params: &ResultsParams<'_>,
+    ) -> Result<QueryResultsResponse, DuneError> {
+        let page_timeout = match self.page_timeout {
+            Some(page_timeout) => page_timeout,
+            None => return self.fetch_results_page(url_path, params).await,
+        };
 
-                rows.extend(response.result.rows);
-                next_offset = response.next_offset;
+        // retry the page as many times as the other requests, so that a dead endpoint can't hang
+        // the download when there is no overall timeout
+        let mut attempt = 0;
+        loop {
+            match tokio::time::timeout(page_timeout, self.fetch_results_page(url_path, params))
+                .await
+            {
+                Ok(res) => return res,
+                Err(_) if attempt >= self.max_retries => {
+                    return Err(DuneError::PageTimeout {
+                        offset: params.get_offset(),
+                        attempts: attempt + 1,
+                    })
+                }
+                Err(_) => {
+                    attempt += 1;
+                    warn!(
+                        "Page at offset {} timed out after {:?}. Retrying ({}/{})...",
+                        params.get_offset(),
+                        page_timeout,
+                        attempt,
+                        self.max_retries
+                    );
+                }
             }
         }
+    }
 
-        Ok(QueryResult { metadata, rows })
+    async fn fetch_results_page(
+        &self,
+        url_path: &str,
+        params: &ResultsParams<'_>,
+    ) -> Result<QueryResultsResponse, DuneError> {
+        let params_encoded = params.url_encode()?;
+
+        let response = self
+            .send_with_retry(
+                self.http
+                    .get(self.url(&format!("{}?{}", url_path, &params_encoded)))
+                    .header(API_KEY_HEADER, &self.api_key),
+            )
+            .await?;
+        trace!("{:#?}", response);
+
+        let res = parse_response::<QueryResultsResponse>(response).await?;
+        trace!("{:#?}", res);
+        Ok(res)
     }
 
+    #[instrument(skip_all, fields(query_id = query_id))]
     pub async fn execute_query_and_get_results_when_ready(
         &self,
         query_id: u64,
         performance: EngineSize,
         params: Option<JsonValue>,
         poll_interval: Option<u64>,
-        peak: bool,
+        max_wait: Option<u64>,
+        peak: Option<u64>,
     ) -> Result<QueryResult, DuneError> {
         match self.execute_query(query_id, performance, params).await {
             Ok(res) => {
                 info!("Query execution successfully submitted: {:?}", res);
-                let mut has_finished = false;
-                let execution_id = res.execution_id;
-                while !has_finished {
-                    info!(
-                        "Query execution not finished yet. Waiting {} seconds...",
-                        poll_interval.unwrap_or(60)
-                    );
-                    tokio::time::sleep(tokio::time::Duration::from_secs(
-                        poll_interval.unwrap_or(60),
-                    ))
-                    .await;
-                    match self.get_execution_status(&execution_id).await {
-                        Ok(res) => match res.status {
-                            ExecutionStatus::QueryStateExecuting => {}
-                            ExecutionStatus::QueryStatePending => {}
-                            ExecutionStatus::QueryStateCompleted => {
-                                info!("Query execution finished!");
-                                has_finished = true;
-                            }
-                            _ => return Err(DuneError::QueryStatusError(res.status)),
-                        },
-                        Err(e) => {
-                            error!("Error when fetching the query results: {:?}", e);
-                            return Err(e);
-                        }
-                    };
-                }
-
-                self.get_query_results(&execution_id, QueryResultsFilter::new(), peak)
-                    .await
+                self.wait_until_finished(&res.execution_id, poll_interval, max_wait)
+                    .await?;
+                self.get_query_results(
+                    &res.execution_id,
+                    QueryResultsFilter::new(),
+                    ResultsOptions::default(),
+                    peak,
+                )
+                .await
             }
             Err(e) => {
                 error!("Error when executing the query: {:?}", e);
-                return Err(e);
+                Err(e)
             }
         }
     }
 
-    pub async fn get_query_results_when_ready(
+    /// Polls the execution status until the execution completes.
+    ///
+    /// The status is checked every `poll_interval` seconds ([`DEFAULT_POLL_INTERVAL`] if
+    /// `None`). Once `max_wait` seconds have passed without the execution completing,
+    /// [`DuneError::PollTimeout`] is returned.
+    #[instrument(skip_all, fields(execution_id = execution_id))]
+    pub async fn wait_until_finished(
         &self,
         execution_id: &str,
         poll_interval: Option<u64>,
-        peak: bool,
-    ) -> Result<QueryResult, DuneError> {
-        let mut has_finished = false;
-        while !has_finished {
-            debug!(
-                "Query execution not finished yet. Waiting {} seconds...",
-                poll_interval.unwrap_or(60)
-            );
-            tokio::time::sleep(tokio::time::Duration::from_secs(
-                poll_interval.unwrap_or(60),
-            ))
-            .await;
-            match self.get_execution_status(&execution_id).await {
-                Ok(res) => match res.status {
-                    ExecutionStatus::QueryStateExecuting => {}
-                    ExecutionStatus::QueryStatePending => {}
-                    ExecutionStatus::QueryStateCompleted => {
+        max_wait: Option<u64>,
+    ) -> Result<(), DuneError> {
+        let poll_interval = poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+        let started_at = Instant::now();
+        let mut last_status = None;
+        loop {
+            match self.get_execution_status(execution_id).await {
+                Ok(res) => {
+                    log_status_transition(execution_id, &res, last_status, started_at);
+                    last_status = Some(res.status);
+                    if res.status.is_success() {
+                        // partial results are still retrievable, which beats failing outright
+                        if res.status == ExecutionStatus::QueryStateCompletedPartial {
+                            warn!(
+                                "Query execution {} completed partially, the results are \
+                                incomplete",
+                                execution_id
+                            );
+                        }
                         debug!("Query execution finished!");
-                        has_finished = true;
+                        return Ok(());
+                    }
+                    if res.status.is_terminal() {
+                        return Err(DuneError::QueryStatusError {
+                            status: res.status,
+                            message: res.error.map(|error| error.message),
+                        });
                     }
-                    _ => return Err(DuneError::QueryStatusError(res.status)),
-                },
+                }
                 Err(e) => {
                     error!("Error when fetching the query results: {:?}", e);
                     return Err(e);
                 }
             };
+
+            if let Some(max_wait) = max_wait {
+                if started_at.elapsed() >= Duration::from_secs(max_wait) {
+                    return Err(DuneError::PollTimeout(execution_id.to_string()));
+                }
+            }
+
+            debug!(
+                "Query execution not finished yet. Waiting {} seconds...",
+                poll_interval
+            );
+            tokio::time::sleep(Duration::from_secs(poll_interval)).await;
         }
+    }
+}
 
-        self.get_query_results(&execution_id, QueryResultsFilter::new(), peak)
-            .await
+// Logs the state of the execution when it changes (e.g. pending -> executing), along with the
+// time elapsed since it was submitted, so that long waits aren't silent. Unchanged states are
+// only logged at DEBUG.
+fn log_status_transition(
+    execution_id: &str,
+    status: &ExecutionStatusResponse,
+    last_status: Option<ExecutionStatus>,
+    started_at: Instant,
+) {
+    // the submit time is only unknown for executions that were never picked up
+    let elapsed = status
+        .submitted_at
+        .and_then(|submitted_at| (Utc::now() - submitted_at).to_std().ok())
+        .unwrap_or_else(|| started_at.elapsed());
+    let elapsed = Duration::from_secs(elapsed.as_secs());
+    let queue_position = match status.queue_position {
+        Some(position) => format!(", queue position {}", position),
+        None => String::new(),
+    };
+    match last_status == Some(status.status) {
+        false => info!(
+            "Execution {} is {:?} ({:?} since submitted{})",
+            execution_id, status.status, elapsed, queue_position
+        ),
+        true => debug!(
+            "Execution {} is still {:?} ({:?} since submitted{})",
+            execution_id, status.status, elapsed, queue_position
+        ),
+    }
+}
+
+/// Fetches the pages of results lazily, following the `next_offset` of each response.
+pub struct ResultsPager<'a> {
+    client: &'a DuneClient,
+    /// Query or execution id, tagging the logs of each page.
+    id: &'a str,
+    url_path: String,
+    params: ResultsParams<'a>,
+    paginate: bool,
+    /// Requested columns, in the order they should be exported.
+    columns: Option<Vec<String>>,
+    /// Whether the requested columns have been checked against the metadata of the results.
+    columns_checked: bool,
+    next_offset: Option<u64>,
+    /// Offset of the first row not fetched yet, even when not paginating.
+    resume_offset: Option<u64>,
+    /// Maximum number of rows to return, across all the pages.
+    max_rows: Option<u64>,
+    /// Number of rows returned so far.
+    rows_returned: u64,
+    /// Offset right after the last row fetched.
+    rows_fetched: u128,
+    total_row_count: u128,
+    /// Datapoints of all the pages fetched so far.
+    datapoint_count: u128,
+    /// Number of pages fetched so far.
+    page_count: u64,
+    /// Whether to log the progress after each page, off when the pages are fetched concurrently.
+    log_progress: bool,
+}
+
+impl ResultsPager<'_> {
+    /// Makes the next page start at `offset`, e.g. to resume an interrupted download.
+    pub fn resume_from(&mut self, offset: u64) {
+        self.next_offset = Some(offset);
+        self.resume_offset = Some(offset);
+    }
+
+    /// Offset of the next page to fetch, `None` once all the pages have been fetched.
+    pub fn next_offset(&self) -> Option<u64> {
+        self.next_offset
+    }
+
+    /// Offset from which an interrupted or single-page retrieval can be resumed, i.e. of the
+    /// first row not fetched yet. `None` once all the rows have been fetched.
+    pub fn resume_offset(&self) -> Option<u64> {
+        self.resume_offset
+    }
+
+    // Rows left to reach the maximum, if any.
+    fn remaining_rows(&self) -> Option<u64> {
+        self.max_rows
+            .map(|max_rows| max_rows.saturating_sub(self.rows_returned))
+    }
+
+    /// Number of rows requested per page, which may have been reduced to stay under the
+    /// datapoint limit.
+    pub fn page_size(&self) -> u64 {
+        self.params.get_limit()
+    }
+
+    /// Number of rows up to the end of the last page fetched, including the rows before
+    /// the starting offset.
+    pub fn rows_fetched(&self) -> u128 {
+        self.rows_fetched
+    }
+
+    /// Total number of rows of the results, as reported by the last page fetched.
+    pub fn total_row_count(&self) -> u128 {
+        self.total_row_count
+    }
+
+    /// Sum of the datapoints of the pages fetched so far, as each page only reports its own.
+    pub fn datapoint_count(&self) -> u128 {
+        self.datapoint_count
+    }
+
+    /// Number of pages fetched so far.
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// Logs the rows, pages and datapoints fetched so far, e.g. at the end of an export.
+    pub fn log_summary(&self) {
+        log_fetch_summary(self.rows_returned, self.page_count, self.datapoint_count);
+    }
+
+    /// Fetches the next page of results, along with its offset.
+    /// Returns `None` once all the pages have been fetched.
+    #[instrument(skip_all, fields(id = self.id, offset))]
+    pub async fn next_page(&mut self) -> Result<Option<(u64, QueryResultsResponse)>, DuneError> {
+        let offset = match self.next_offset {
+            Some(offset) if self.remaining_rows() != Some(0) => offset,
+            _ => return Ok(None),
+        };
+        tracing::Span::current().record("offset", offset);
+        self.params.update_offset(offset);
+        // don't fetch (and pay for) more rows than are left to reach the maximum
+        if let Some(remaining) = self.remaining_rows() {
+            if remaining < self.params.get_limit() {
+                self.params.update_limit(remaining);
+            }
+        }
+
+        let mut response = loop {
+            match self
+                .client
+                .get_results_page(&self.url_path, &self.params)
+                .await
+            {
+                // retry the same offset with a smaller page, which is kept for the next pages
+                Err(DuneError::DatapointLimitExceeded(message))
+                    if self.params.get_limit() > MIN_PAGE_SIZE =>
+                {
+                    let limit = (self.params.get_limit() / 2).max(MIN_PAGE_SIZE);
+                    warn!(
+                        "{}. Retrying offset {} with a page size of {}...",
+                        message, offset, limit
+                    );
+                    // the single page would miss rows, so the requested ones are fetched over
+                    // several pages instead
+                    if !self.paginate {
+                        self.paginate = true;
+                        self.max_rows = Some(self.rows_returned + self.params.get_limit());
+                    }
+                    self.params.update_limit(limit);
+                }
+                res => break res?,
+            }
+        };
+        check_results_state(&response)?;
+
+        // a mistyped column silently returns nothing, so warn about the unknown ones
+        if let Some(columns) = &self.columns {
+            let available = response.result.metadata.column_names.join(", ");
+            let missing = response.result.metadata.project(columns);
+            if !missing.is_empty() && !self.columns_checked {
+                warn!(
+                    "Unknown columns requested: {}. Available columns: {}",
+                    missing.join(", "),
+                    available
+                );
+            }
+            self.columns_checked = true;
+        }
+
+        // trim the page if it overshoots the maximum anyway
+        let mut next_offset = response.next_offset;
+        if let Some(remaining) = self.remaining_rows() {
+            if response.result.rows.len() as u64 > remaining {
+                response.result.rows.truncate(remaining as usize);
+                next_offset = Some(offset + remaining);
+            }
+        }
+        self.rows_returned += response.result.rows.len() as u64;
+
+        self.next_offset = match self.paginate && self.remaining_rows() != Some(0) {
+            true => next_offset,
+            false => None,
+        };
+        self.resume_offset = next_offset;
+        debug!("next_offset: {:?}", self.next_offset);
+
+        self.rows_fetched = u128::from(offset) + response.result.rows.len() as u128;
+        self.total_row_count = response.result.metadata.total_row_count;
+        self.datapoint_count += response.result.metadata.datapoint_count;
+        self.page_count += 1;
+        if self.paginate && self.log_progress {
+            log_progress(self.rows_fetched, self.total_row_count);
+        }
+
+        Ok(Some((offset, response)))
+    }
+}
+
+// The datapoints are those reported by the pages, which the credits spent are based on.
+fn log_fetch_summary(row_count: u64, page_count: u64, datapoint_count: u128) {
+    info!(
+        "Fetched {} rows across {} page{}, ~{} datapoints",
+        row_count,
+        page_count,
+        if page_count == 1 { "" } else { "s" },
+        datapoint_count
+    );
+}
+
+// Builds the HTTP client shared by the requests of a `DuneClient`.
+fn http_client(timeout: Duration, user_agent: &str, headers: &HeaderMap) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(timeout)
+        .user_agent(user_agent)
+        .default_headers(headers.clone())
+        // sets `Accept-Encoding`, so that large result pages are sent compressed
+        .gzip(true)
+        .brotli(true)
+        .build()
+        .expect("Failed to build the HTTP client")
+}
+
+fn log_progress(rows_fetched: u128, total_row_count: u128) {
+    if total_row_count > 0 {
+        info!(
+            "{} / {} rows ({:.1}%)",
+            rows_fetched,
+            total_row_count,
+            rows_fetched as f64 * 100.0 / total_row_count as f64
+        );
+    }
+}
+
+// Logs the method, URL, headers and body of the request, with the API key redacted.
+// The request is only built when TRACE is enabled, since that requires cloning it.
+fn trace_request(request: &reqwest::RequestBuilder) {
+    if !tracing::enabled!(tracing::Level::TRACE) {
+        return;
+    }
+    if let Some(description) = describe_request(request) {
+        trace!("Request: {}", description);
+    }
+}
+
+// Renders the method, URL, headers and body of the request, with the API key redacted.
+// Returns `None` if the request can't be cloned (streaming body) or built.
+fn describe_request(request: &reqwest::RequestBuilder) -> Option<String> {
+    let request = request.try_clone()?.build().ok()?;
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+    Some(format!(
+        "{} {}\n{}\n{}",
+        request.method(),
+        request.url(),
+        redact_headers(request.headers()),
+        body
+    ))
+}
+
+// Renders the headers one per line, masking the API key.
+fn redact_headers(headers: &reqwest::header::HeaderMap) -> String {
+    headers
+        .iter()
+        .map(
+            |(name, value)| match name.as_str().eq_ignore_ascii_case(API_KEY_HEADER) {
+                true => format!("{}: ***", name),
+                false => format!("{}: {}", name, value.to_str().unwrap_or("<binary>")),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Parses the JSON body of the response, mapping known error statuses to their `DuneError`.
+async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, DuneError> {
+    let status = response.status();
+    if status.is_success() {
+        let body = response.bytes().await?;
+        return serde_json::from_slice::<T>(&body).map_err(|e| parse_error(status, e, &body));
+    }
+
+    Err(error_response(response).await)
+}
+
+// A body that doesn't have the expected shape may still be an error object, e.g.
+// `{"error": "..."}`. Otherwise, the start of the body is kept to tell what was returned instead.
+fn parse_error(status: StatusCode, source: serde_json::Error, body: &[u8]) -> DuneError {
+    let body = String::from_utf8_lossy(body);
+    if let Some(message) = api_error(&body) {
+        return DuneError::ApiError {
+            status: status.as_u16(),
+            message,
+        };
+    }
+
+    let mut end = body.len().min(MAX_BODY_PREFIX);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let prefix = match end < body.len() {
+        true => format!("{}...", &body[..end]),
+        false => body.into_owned(),
+    };
+    DuneError::ParseError {
+        source,
+        body: Some(prefix),
+    }
+}
+
+// Maps a non-success response to its `DuneError`.
+async fn error_response(response: reqwest::Response) -> DuneError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    let message = error_message(&body);
+    match status {
+        StatusCode::PAYMENT_REQUIRED => DuneError::PaymentRequired(message),
+        StatusCode::NOT_FOUND => DuneError::NotFound(message),
+        StatusCode::BAD_REQUEST if is_datapoint_limit_error(&message) => {
+            DuneError::DatapointLimitExceeded(message)
+        }
+        _ => DuneError::ApiError {
+            status: status.as_u16(),
+            message,
+        },
+    }
+}
+
+fn error_message(body: &str) -> String {
+    api_error(body).unwrap_or_else(|| body.to_string())
+}
+
+// Message of the API's error envelope, if the body is one. An `error` that isn't a string
+// (e.g. an object with more details) is kept as JSON.
+fn api_error(body: &str) -> Option<String> {
+    if let Ok(api_error) = serde_json::from_str::<ApiErrorResponse>(body) {
+        return Some(api_error.error);
+    }
+    match serde_json::from_str::<JsonValue>(body).ok()?.get("error")? {
+        JsonValue::Null => None,
+        error => Some(error.to_string()),
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Errors that may not happen again, e.g. a refused connection or a timeout. Others, such as
+// invalid URLs, redirect loops or undecodable bodies, would fail the same way on every retry.
+fn is_retryable_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    parse_retry_after(
+        response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)?
+            .to_str()
+            .ok()?,
+    )
+}
+
+// Only the delay-seconds form of `Retry-After` is supported, HTTP dates are ignored. The delay
+// is capped like the backoff, so that a server can't stall the client indefinitely.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let secs = value.trim().parse::<u64>().ok()?;
+    Some(Duration::from_secs(secs).min(MAX_RETRY_DELAY))
+}
+
+fn backoff_delay(base_delay: Duration, attempt: u32) -> Duration {
+    base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY)
+}
+
+// Dune rejects pages with too many datapoints (rows * columns) with a 400,
+// mentioning the datapoints limit in the error message.
+fn is_datapoint_limit_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("datapoint") || message.contains("data point")
+}
+
+// Builds the results endpoint path and params for the given id. See `ResultId` for how query
+// and execution ids are told apart; ids that are neither are sent to the execution endpoint.
+fn results_request(
+    id: &str,
+    ignore_max: bool,
+    offset: u64,
+    limit: u64,
+    columns: Option<Vec<String>>,
+    filters: QueryResultsFilter,
+    sort_by: Option<String>,
+) -> (String, ResultsParams<'_>) {
+    match id.parse::<ResultId>() {
+        Ok(ResultId::Query(query_id)) => (
+            format!("v1/query/{}/results", query_id),
+            ResultsParams::new_query(
+                query_id, ignore_max, offset, limit, columns, filters, sort_by,
+            ),
+        ),
+        Ok(ResultId::Execution(_)) | Err(_) => (
+            format!("v1/execution/{}/results", id),
+            ResultsParams::new_execution(id, ignore_max, offset, limit, columns, filters, sort_by),
+        ),
+    }
+}
+
+/// Dune expects the query parameters to be a JSON object of parameter names and values.
+fn validate_params(params: Option<&JsonValue>) -> Result<(), DuneError> {
+    let kind = match params {
+        None | Some(JsonValue::Object(_)) => return Ok(()),
+        Some(JsonValue::Null) => "null",
+        Some(JsonValue::Bool(_)) => "a boolean",
+        Some(JsonValue::Number(_)) => "a number",
+        Some(JsonValue::String(_)) => "a string",
+        Some(JsonValue::Array(_)) => "an array",
+    };
+    Err(DuneError::InvalidParams(format!(
+        "expected a JSON object, got {}",
+        kind
+    )))
+}
+
+/// Only completed (or partially completed) executions have rows to extract: a failed or
+/// cancelled execution is also reported as finished, but its results are empty.
+fn check_results_state(response: &QueryResultsResponse) -> Result<(), DuneError> {
+    match response.status() {
+        Some(ExecutionStatus::QueryStateCompleted)
+        | Some(ExecutionStatus::QueryStateCompletedPartial) => Ok(()),
+        Some(ExecutionStatus::QueryStateExpired) => Err(DuneError::ResultsExpired {
+            execution_id: response.execution_id.clone(),
+        }),
+        Some(status @ ExecutionStatus::QueryStateFailed)
+        | Some(status @ ExecutionStatus::QueryStateCancelled) => Err(DuneError::QueryStatusError {
+            status,
+            message: response.error.as_ref().map(|error| error.message.clone()),
+        }),
+        Some(_) => Err(DuneError::QueryNotFinished),
+        None if !response.is_execution_finished => Err(DuneError::QueryNotFinished),
+        None => {
+            warn!("unknown execution state: {}", response.state);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results_response(state: &str, is_execution_finished: bool) -> QueryResultsResponse {
+        serde_json::from_value(serde_json::json!({
+            "state": state,
+            "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+            "is_execution_finished": is_execution_finished,
+            "next_offset": null,
+            "query_id": 3998990,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_results_state() {
+        assert!(check_results_state(&results_response("QUERY_STATE_COMPLETED", true)).is_ok());
+        assert!(
+            check_results_state(&results_response("QUERY_STATE_COMPLETED_PARTIAL", true)).is_ok()
+        );
+        assert!(matches!(
+            check_results_state(&results_response("QUERY_STATE_FAILED", true)),
+            Err(DuneError::QueryStatusError {
+                status: ExecutionStatus::QueryStateFailed,
+                message: None,
+            })
+        ));
+        assert!(matches!(
+            check_results_state(&results_response("QUERY_STATE_CANCELLED", true)),
+            Err(DuneError::QueryStatusError {
+                status: ExecutionStatus::QueryStateCancelled,
+                ..
+            })
+        ));
+
+        // the reason of the failure is kept
+        let mut response = results_response("QUERY_STATE_FAILED", true);
+        response.error = Some(ExecutionError {
+            error_type: Some("FAILED_TYPE_EXECUTION_FAILED".to_string()),
+            message: "query exceeded memory limit".to_string(),
+            metadata: None,
+        });
+        match check_results_state(&response) {
+            Err(DuneError::QueryStatusError { message, .. }) => {
+                assert_eq!(message.as_deref(), Some("query exceeded memory limit"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            check_results_state(&results_response("QUERY_STATE_EXPIRED", true)),
+            Err(DuneError::ResultsExpired { .. })
+        ));
+        assert!(matches!(
+            check_results_state(&results_response("QUERY_STATE_EXECUTING", false)),
+            Err(DuneError::QueryNotFinished)
+        ));
+    }
+
+    #[test]
+    fn test_redact_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-dune-api-key", "secret".parse().unwrap());
+        headers.insert("content-type", "application/json".parse().unwrap());
+        let redacted = redact_headers(&headers);
+        assert!(!redacted.contains("secret"));
+        assert!(redacted.contains("x-dune-api-key: ***"));
+        assert!(redacted.contains("content-type: application/json"));
+    }
+
+    #[test]
+    fn test_validate_params() {
+        assert!(validate_params(None).is_ok());
+        assert!(validate_params(Some(&serde_json::json!({"min_value": 1}))).is_ok());
+        match validate_params(Some(&serde_json::json!([1, 2]))) {
+            Err(DuneError::InvalidParams(message)) => {
+                assert_eq!(message, "expected a JSON object, got an array")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(validate_params(Some(&JsonValue::from("min_value=1"))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_results() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let body = serde_json::json!({
+            "state": "QUERY_STATE_COMPLETED",
+            "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+            "is_execution_finished": true,
+            "next_offset": null,
+            "query_id": 1,
+            "result": {
+                "rows": [{"value": 1}, {"value": 2}, {"value": 3}],
+                "metadata": {
+                    "column_names": ["value"],
+                    "column_types": ["integer"],
+                    "row_count": 3,
+                    "result_set_bytes": 24,
+                    "total_row_count": 3,
+                    "total_result_set_bytes": 24,
+                    "datapoint_count": 3,
+                    "pending_time_millis": 10,
+                    "execution_time_millis": 100
+                }
+            }
+        })
+        .to_string();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(encoder.finish().unwrap()),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/2/results"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let compressed = client
+            .get_query_results("1", QueryResultsFilter::new(), Default::default(), None)
+            .await
+            .unwrap();
+        let uncompressed = client
+            .get_query_results("2", QueryResultsFilter::new(), Default::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(compressed.rows.len(), 3);
+        assert_eq!(compressed.rows, uncompressed.rows);
+
+        let requests = server.received_requests().await.unwrap();
+        let accept_encoding = requests[0].headers.get("accept-encoding").unwrap();
+        assert!(accept_encoding.to_str().unwrap().contains("gzip"));
+        assert!(accept_encoding.to_str().unwrap().contains("br"));
+    }
+
+    #[tokio::test]
+    async fn test_results_csv() {
+        use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results/csv"))
+            .and(query_param_is_missing("offset"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-dune-next-offset", "2")
+                    .set_body_string("block,value\n1,a\n2,b\n"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results/csv"))
+            .and(query_param("offset", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("block,value\n3,c\n"))
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let mut csv = Vec::new();
+        let written = client.get_query_results_csv("1", &mut csv).await.unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "block,value\n1,a\n2,b\n3,c\n"
+        );
+        assert_eq!(written, 24);
+    }
+
+    // Page of results with one `value` column, holding the given values.
+    fn results_page(
+        values: Vec<u64>,
+        next_offset: Option<u64>,
+        total_row_count: u64,
+        is_execution_finished: bool,
+    ) -> JsonValue {
+        let rows: Vec<JsonValue> = values
+            .iter()
+            .map(|value| serde_json::json!({ "value": value }))
+            .collect();
+        serde_json::json!({
+            "state": match is_execution_finished {
+                true => "QUERY_STATE_COMPLETED",
+                false => "QUERY_STATE_EXECUTING",
+            },
+            "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+            "is_execution_finished": is_execution_finished,
+            "next_offset": next_offset,
+            "query_id": 1,
+            "result": {
+                "rows": rows,
+                "metadata": {
+                    "column_names": ["value"],
+                    "column_types": ["integer"],
+                    "row_count": values.len(),
+                    "result_set_bytes": 0,
+                    "total_row_count": total_row_count,
+                    "total_result_set_bytes": 0,
+                    "datapoint_count": values.len(),
+                    "pending_time_millis": 0,
+                    "execution_time_millis": 0
+                }
+            }
+        })
+    }
+
+    // Serves the pages of the results of query 1, keyed by their offset.
+    async fn mock_results_pages(pages: Vec<(u64, JsonValue)>) -> wiremock::MockServer {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (offset, page) in pages {
+            Mock::given(method("GET"))
+                .and(path("/v1/query/1/results"))
+                .and(query_param("offset", offset.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(page))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let first_page: Vec<u64> = (0..1000).collect();
+        let server = mock_results_pages(vec![
+            (0, results_page(first_page, Some(1000), 1002, true)),
+            (1000, results_page(vec![1000, 1001], None, 1002, true)),
+        ])
+        .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let result = client
+            .get_query_results("1", QueryResultsFilter::new(), Default::default(), None)
+            .await
+            .unwrap();
+        let values: Vec<u64> = result
+            .rows
+            .iter()
+            .map(|row| row["value"].as_u64().unwrap())
+            .collect();
+        assert_eq!(values, (0..1002).collect::<Vec<u64>>());
+        // each page reports its own datapoints, which add up to those of all the rows
+        assert_eq!(result.metadata.datapoint_count, 1002);
+        assert_eq!(result.page_count, 2);
+        assert_eq!(result.next_offset, None);
+        // each page is fetched exactly once, and the loop stops after the last one
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_parallel_pagination() {
+        let server = mock_results_pages(vec![
+            (0, results_page(vec![0, 1], Some(2), 5, true)),
+            (2, results_page(vec![2, 3], Some(4), 5, true)),
+            (4, results_page(vec![4], None, 5, true)),
+        ])
+        .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let options = ResultsOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let result = client
+            .get_query_results_parallel("1", QueryResultsFilter::new(), options, 2)
+            .await
+            .unwrap();
+        let values: Vec<u64> = result
+            .rows
+            .iter()
+            .map(|row| row["value"].as_u64().unwrap())
+            .collect();
+        assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        assert_eq!(result.metadata.datapoint_count, 5);
+        assert_eq!(result.page_count, 3);
+        assert_eq!(result.next_offset, None);
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_pagination_not_finished() {
+        let first_page: Vec<u64> = (0..1000).collect();
+        let server = mock_results_pages(vec![
+            (0, results_page(first_page, Some(1000), 1002, true)),
+            (1000, results_page(vec![], None, 0, false)),
+        ])
+        .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let result = client
+            .get_query_results("1", QueryResultsFilter::new(), Default::default(), None)
+            .await;
+        assert!(matches!(result, Err(DuneError::QueryNotFinished)));
+    }
+
+    #[tokio::test]
+    async fn test_max_rows() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let page = |rows: Vec<u64>, next_offset| results_page(rows, next_offset, 10, true);
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .and(query_param("offset", "0"))
+            .and(query_param("limit", "3"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(vec![0, 1, 2], Some(3))))
+            .mount(&server)
+            .await;
+        // the last page is shrunk to the rows left
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .and(query_param("offset", "3"))
+            .and(query_param("limit", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(vec![3, 4], Some(5))))
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let options = ResultsOptions {
+            limit: Some(3),
+            max_rows: Some(5),
+            ..Default::default()
+        };
+        let result = client
+            .get_query_results("1", QueryResultsFilter::new(), options, None)
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 5);
+        assert_eq!(result.next_offset, Some(5));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_peak_limit() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for limit in [5, 50] {
+            Mock::given(method("GET"))
+                .and(path("/v1/query/1/results"))
+                .and(query_param("limit", limit.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(results_page(
+                    vec![0, 1],
+                    Some(2),
+                    100,
+                    true,
+                )))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        // the peak count is the size of the single page, unless a limit is given
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        for limit in [None, Some(50)] {
+            let options = ResultsOptions {
+                limit,
+                ..Default::default()
+            };
+            let result = client
+                .get_query_results("1", QueryResultsFilter::new(), options, Some(5))
+                .await
+                .unwrap();
+            assert_eq!(result.rows.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let server = wiremock::MockServer::start().await;
+        let client = DuneClient::with_base_url(String::new(), &server.uri()).with_dry_run(true);
+        assert!(matches!(
+            client.execute_query(1, EngineSize::Medium, None).await,
+            Err(DuneError::DryRun)
+        ));
+        assert!(matches!(
+            client
+                .get_execution_status("01J5ZMD33P6J413G1KQM6QTE4S")
+                .await,
+            Err(DuneError::DryRun)
+        ));
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_base_url_trailing_slash() {
+        for base_url in ["https://x/api", "https://x/api/"] {
+            let client = DuneClient::with_base_url(String::new(), base_url);
+            assert_eq!(
+                client.url("v1/query/1/results"),
+                "https://x/api/v1/query/1/results"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 2 "), Some(Duration::from_secs(2)));
+        assert_eq!(parse_retry_after("86400"), Some(MAX_RETRY_DELAY));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_rate_limit() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(results_page(
+                vec![0],
+                None,
+                1,
+                true,
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // the backoff would wait far longer than the test, so `Retry-After` must be honored
+        let client = DuneClient::with_base_url(String::new(), &server.uri())
+            .with_retry_base_delay(Duration::from_secs(3600));
+        let results = client.get_query_results(
+            "1",
+            QueryResultsFilter::new(),
+            ResultsOptions::default(),
+            None,
+        );
+        let result = tokio::time::timeout(Duration::from_secs(5), results)
+            .await
+            .expect("Retry-After wasn't honored")
+            .unwrap();
+        assert_eq!(result.rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri())
+            .with_max_retries(2)
+            .with_retry_base_delay(Duration::from_millis(1));
+        let res = client
+            .get_query_results(
+                "1",
+                QueryResultsFilter::new(),
+                ResultsOptions::default(),
+                None,
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(DuneError::RetriesExhausted {
+                attempts: 3,
+                last_status: Some(503),
+                last_error: None,
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_connection_errors() {
+        // nothing listens on the port once the listener is dropped, so connections are refused
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let client = DuneClient::with_base_url(String::new(), &base_url)
+            .with_max_retries(1)
+            .with_retry_base_delay(Duration::from_millis(1));
+        let res = client.get_account_info().await;
+        assert!(matches!(
+            res,
+            Err(DuneError::RetriesExhausted {
+                attempts: 2,
+                last_status: None,
+                last_error: Some(e),
+            }) if e.is_connect()
+        ));
+
+        // an invalid URL fails the same way every time, so it isn't retried
+        let client = DuneClient::with_base_url(String::new(), "not a url")
+            .with_retry_base_delay(Duration::from_secs(3600));
+        let res = tokio::time::timeout(Duration::from_secs(5), client.get_account_info())
+            .await
+            .expect("a request that can't succeed was retried");
+        assert!(matches!(res, Err(DuneError::RequestError(e)) if e.is_builder()));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let base_delay = Duration::from_millis(500);
+        assert_eq!(backoff_delay(base_delay, 0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(base_delay, 3), Duration::from_secs(4));
+        assert_eq!(backoff_delay(base_delay, 64), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let source = || serde_json::from_str::<QueryResultsResponse>("{}").unwrap_err();
+
+        let body = br#"{"error": "Query not found"}"#;
+        assert!(matches!(
+            parse_error(StatusCode::OK, source(), body),
+            DuneError::ApiError { status: 200, message } if message == "Query not found"
+        ));
+
+        let body = format!(r#"{{"rows": "{}"}}"#, "é".repeat(MAX_BODY_PREFIX));
+        match parse_error(StatusCode::OK, source(), body.as_bytes()) {
+            DuneError::ParseError {
+                body: Some(prefix), ..
+            } => {
+                assert!(prefix.starts_with(r#"{"rows": "éé"#));
+                assert!(prefix.ends_with("..."));
+                assert!(prefix.len() <= MAX_BODY_PREFIX + 3);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error_message(r#"{"error":"invalid API Key"}"#),
+            "invalid API Key"
+        );
+        assert_eq!(error_message("Bad Gateway"), "Bad Gateway");
+        assert_eq!(
+            error_message(r#"{"error":{"code":"quota"}}"#),
+            r#"{"code":"quota"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_query_id_by_name() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let queries = |offset: u64, count: u64| {
+            (offset..offset + count)
+                .map(|id| serde_json::json!({"id": id, "name": format!("query {}", id)}))
+                .collect::<Vec<_>>()
+        };
+        let mut last_page = queries(100, 2);
+        last_page.push(serde_json::json!({"id": 7, "name": "Duplicate"}));
+        last_page.push(serde_json::json!({"id": 8, "name": "duplicate"}));
+        last_page.push(serde_json::json!({"id": 9, "name": "DUPLICATE"}));
+        for (offset, page) in [(0, queries(0, 100)), (100, last_page)] {
+            Mock::given(method("GET"))
+                .and(path("/v1/queries"))
+                .and(query_param("offset", offset.to_string()))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({"queries": page, "total": 105})),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        assert_eq!(
+            client.find_query_id_by_name("query 101").await.unwrap(),
+            101
+        );
+        assert_eq!(client.find_query_id_by_name("QUERY 5").await.unwrap(), 5);
+        assert_eq!(client.find_query_id_by_name("duplicate").await.unwrap(), 8);
+        assert!(matches!(
+            client.find_query_id_by_name("Duplicates").await,
+            Err(DuneError::NotFound(_))
+        ));
+        match client.find_query_id_by_name("DupLicate").await {
+            Err(DuneError::AmbiguousQueryName { candidates, .. }) => {
+                let ids: Vec<u64> = candidates.iter().map(|query| query.id).collect();
+                assert_eq!(ids, vec![7, 8, 9]);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_sql() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/sql/execute"))
+            .and(body_json(
+                serde_json::json!({"sql": "SELECT 1", "performance": "large"}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+                "state": "QUERY_STATE_PENDING"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let res = client
+            .execute_sql("SELECT 1", EngineSize::Large)
+            .await
+            .unwrap();
+        assert_eq!(res.execution_id, "01J5ZMD33P6J413G1KQM6QTE4S");
+        assert_eq!(res.status, ExecutionStatus::QueryStatePending);
+    }
+
+    #[tokio::test]
+    async fn test_page_timeout() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        // a dead endpoint, which never answers within the page timeout
+        Mock::given(method("GET"))
+            .and(path("/v1/query/1/results"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(results_page(vec![0], None, 1, true))
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri())
+            .with_page_timeout(Duration::from_millis(50))
+            .with_max_retries(2);
+        let res = client
+            .get_query_results(
+                "1",
+                QueryResultsFilter::new(),
+                ResultsOptions::default(),
+                None,
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(DuneError::PageTimeout {
+                offset: 0,
+                attempts: 3
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_and_headers() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let submitted = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+            "state": "QUERY_STATE_PENDING"
+        }));
+        Mock::given(method("POST"))
+            .and(path("/v1/sql/execute"))
+            .and(header("user-agent", DEFAULT_USER_AGENT))
+            .respond_with(submitted.clone())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/sql/execute"))
+            .and(header("user-agent", "dashboards/1.2"))
+            .and(header("x-gateway-token", "secret"))
+            .respond_with(submitted)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        client
+            .execute_sql("SELECT 1", EngineSize::Medium)
+            .await
+            .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-gateway-token", "secret".parse().unwrap());
+        let client = DuneClient::with_base_url(String::new(), &server.uri())
+            .with_user_agent("dashboards/1.2")
+            .with_headers(headers);
+        client
+            .execute_sql("SELECT 1", EngineSize::Medium)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_query() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        // only the given fields are sent, so that the others are kept
+        Mock::given(method("PATCH"))
+            .and(path("/v1/query/42"))
+            .and(body_json(serde_json::json!({"query_sql": "SELECT 2"})))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"query_id": 42})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let changes = UpdateQueryParams {
+            query_sql: Some("SELECT 2".to_string()),
+            ..Default::default()
+        };
+        let res = client.update_query(42, changes).await.unwrap();
+        assert_eq!(res.query_id, 42);
+    }
+
+    #[tokio::test]
+    async fn test_api_error_envelope() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/query/1/execute"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(serde_json::json!({"error": "invalid API Key"})),
+            )
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let result = client.execute_query(1, EngineSize::Medium, None).await;
+        assert!(matches!(
+            result,
+            Err(DuneError::ApiError { status: 401, message }) if message == "invalid API Key"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_datapoint_limit_recovery() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let limit_exceeded = ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "error": "Result exceeds the maximum of 250000 datapoints per request"
+        }));
+        let mock_page = |id: u64, offset: u64, limit: u64, response: ResponseTemplate| {
+            Mock::given(method("GET"))
+                .and(path(format!("/v1/query/{}/results", id)))
+                .and(query_param("offset", offset.to_string()))
+                .and(query_param("limit", limit.to_string()))
+                .respond_with(response)
+                .expect(1)
+        };
+        // the same offset is retried with half the page size, which is kept for the next page
+        mock_page(1, 0, 400, limit_exceeded.clone())
+            .mount(&server)
+            .await;
+        let page = |values: std::ops::Range<u64>, next_offset, total_row_count| {
+            ResponseTemplate::new(200).set_body_json(results_page(
+                values.collect(),
+                next_offset,
+                total_row_count,
+                true,
+            ))
+        };
+        mock_page(1, 0, 200, page(0..200, Some(200), 300))
+            .mount(&server)
+            .await;
+        mock_page(1, 200, 200, page(200..300, None, 300))
+            .mount(&server)
+            .await;
+        // at the minimum page size, the error is returned
+        mock_page(2, 0, MIN_PAGE_SIZE, limit_exceeded.clone())
+            .mount(&server)
+            .await;
+        // a single page that is halved is completed by the next one, but no more
+        mock_page(3, 0, 400, limit_exceeded).mount(&server).await;
+        mock_page(3, 0, 200, page(0..200, Some(200), 1000))
+            .mount(&server)
+            .await;
+        mock_page(3, 200, 200, page(200..400, Some(400), 1000))
+            .mount(&server)
+            .await;
+
+        let client = DuneClient::with_base_url(String::new(), &server.uri());
+        let options = |limit| ResultsOptions {
+            limit: Some(limit),
+            ..Default::default()
+        };
+        let result = client
+            .get_query_results("1", QueryResultsFilter::new(), options(400), None)
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 300);
+
+        let res = client
+            .get_query_results("2", QueryResultsFilter::new(), options(MIN_PAGE_SIZE), None)
+            .await;
+        assert!(matches!(res, Err(DuneError::DatapointLimitExceeded(_))));
+
+        let result = client
+            .get_query_results("3", QueryResultsFilter::new(), options(400), Some(10))
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 400);
+        assert_eq!(result.next_offset, Some(400));
+    }
+
+    #[test]
+    fn test_is_datapoint_limit_error() {
+        assert!(is_datapoint_limit_error(
+            "Result exceeds the maximum of 250000 datapoints per request"
+        ));
+        assert!(is_datapoint_limit_error("Too many data points requested"));
+        assert!(!is_datapoint_limit_error("Invalid API Key"));
     }
 }
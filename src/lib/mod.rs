@@ -1,2 +1,4 @@
 pub mod client;
+pub mod pagination;
+pub mod schema;
 pub mod types;
@@ -0,0 +1,82 @@
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+/// Collects pages of results keyed by their offset, so that rows are assembled
+/// in the order requested from the API (e.g. `sort_by`) even if pages complete out of order.
+#[derive(Debug, Default)]
+pub struct PageCollector {
+    pages: BTreeMap<u64, Vec<JsonValue>>,
+}
+
+impl PageCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the rows of the page starting at `offset`.
+    /// If the page was already collected (e.g. it was retried), it is replaced.
+    pub fn insert(&mut self, offset: u64, rows: Vec<JsonValue>) {
+        self.pages.insert(offset, rows);
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.pages.values().map(Vec::len).sum()
+    }
+
+    /// Consumes the collector, returning all the rows ordered by page offset.
+    pub fn into_rows(self) -> Vec<JsonValue> {
+        self.pages.into_values().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pages(page_count: u64, page_size: u64) -> Vec<(u64, Vec<JsonValue>)> {
+        (0..page_count)
+            .map(|page| {
+                let offset = page * page_size;
+                let rows = (offset..offset + page_size)
+                    .map(|i| json!({ "row": i }))
+                    .collect();
+                (offset, rows)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_out_of_order_pages_keep_sequential_order() {
+        let pages = pages(5, 3);
+
+        let mut sequential = PageCollector::new();
+        for (offset, rows) in pages.clone() {
+            sequential.insert(offset, rows);
+        }
+
+        // simulate concurrent fetches completing in a shuffled order
+        let mut shuffled = PageCollector::new();
+        for i in [3, 0, 4, 1, 2] {
+            let (offset, rows) = pages[i].clone();
+            shuffled.insert(offset, rows);
+        }
+
+        assert_eq!(shuffled.page_count(), 5);
+        assert_eq!(shuffled.row_count(), 15);
+        assert_eq!(shuffled.into_rows(), sequential.into_rows());
+    }
+
+    #[test]
+    fn test_retried_page_is_replaced() {
+        let mut collector = PageCollector::new();
+        collector.insert(0, vec![json!({ "row": "stale" })]);
+        collector.insert(0, vec![json!({ "row": 0 })]);
+
+        assert_eq!(collector.into_rows(), vec![json!({ "row": 0 })]);
+    }
+}
@@ -0,0 +1,307 @@
+#![allow(dead_code)]
+
+use clap::ValueEnum;
+use serde_json::Value as JsonValue;
+
+// DUNE COLUMN TYPES
+
+/// Column type of a Dune query result, parsed from `column_types`.
+///
+/// Dune runs on Trino, so the raw strings are Trino type names such as `varbinary`,
+/// `decimal(38,0)` or `timestamp(3) with time zone`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnType {
+    Boolean,
+    Integer,
+    BigInt,
+    Double,
+    Decimal,
+    Int256,
+    UInt256,
+    Varchar,
+    Varbinary,
+    Date,
+    Timestamp,
+    TimestampTz,
+    /// `array(..)`, `map(..)` and `row(..)` types.
+    Nested,
+    Unknown(String),
+}
+
+impl From<&str> for ColumnType {
+    fn from(s: &str) -> Self {
+        let s = s.trim().to_lowercase();
+        // drop the precision/parameters, e.g. `decimal(38,0)` -> `decimal`
+        let base = s.split('(').next().unwrap_or_default().trim();
+        match base {
+            "boolean" => ColumnType::Boolean,
+            "tinyint" | "smallint" | "integer" | "int" => ColumnType::Integer,
+            "bigint" => ColumnType::BigInt,
+            "double" | "real" => ColumnType::Double,
+            "decimal" => ColumnType::Decimal,
+            "int256" => ColumnType::Int256,
+            "uint256" => ColumnType::UInt256,
+            "varchar" | "char" | "json" => ColumnType::Varchar,
+            "varbinary" => ColumnType::Varbinary,
+            "date" => ColumnType::Date,
+            "timestamp" if s.ends_with("with time zone") => ColumnType::TimestampTz,
+            "timestamp" => ColumnType::Timestamp,
+            "array" | "map" | "row" => ColumnType::Nested,
+            _ => ColumnType::Unknown(s),
+        }
+    }
+}
+
+impl ColumnType {
+    pub fn postgres_type(&self) -> &str {
+        match self {
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::Integer => "INTEGER",
+            ColumnType::BigInt => "BIGINT",
+            ColumnType::Double => "DOUBLE PRECISION",
+            ColumnType::Decimal => "NUMERIC",
+            ColumnType::Int256 | ColumnType::UInt256 => "NUMERIC(78, 0)",
+            ColumnType::Varchar => "TEXT",
+            ColumnType::Varbinary => "BYTEA",
+            ColumnType::Date => "DATE",
+            ColumnType::Timestamp => "TIMESTAMP",
+            ColumnType::TimestampTz => "TIMESTAMPTZ",
+            ColumnType::Nested => "JSONB",
+            ColumnType::Unknown(_) => "TEXT",
+        }
+    }
+
+    pub fn sqlite_type(&self) -> &str {
+        match self {
+            ColumnType::Boolean | ColumnType::Integer | ColumnType::BigInt => "INTEGER",
+            ColumnType::Double => "REAL",
+            // 256-bit integers don't fit into SQLite's 64-bit INTEGER
+            ColumnType::Decimal | ColumnType::Int256 | ColumnType::UInt256 => "NUMERIC",
+            ColumnType::Varbinary => "BLOB",
+            ColumnType::Varchar
+            | ColumnType::Date
+            | ColumnType::Timestamp
+            | ColumnType::TimestampTz
+            | ColumnType::Nested
+            | ColumnType::Unknown(_) => "TEXT",
+        }
+    }
+}
+
+// TYPED VALUES
+
+/// Cell of a result row, coerced to the type of its column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    /// Text, and numbers that don't fit into an `i64` (e.g. `uint256` or `decimal` values).
+    Text(String),
+    /// `varbinary` values, decoded from their `0x`-prefixed hex string.
+    Bytes(Vec<u8>),
+    /// `date` and `timestamp` values, as returned by Dune (e.g. `2024-08-23 12:46:55.606 UTC`).
+    Timestamp(String),
+}
+
+impl TypedValue {
+    /// Coerces the JSON value into the type of its column. Values that don't match the
+    /// column type are kept as text rather than dropped.
+    pub fn from_json(value: &JsonValue, column_type: &ColumnType) -> Self {
+        let text = || match value {
+            JsonValue::String(s) => TypedValue::Text(s.clone()),
+            value => TypedValue::Text(value.to_string()),
+        };
+        match (column_type, value) {
+            (_, JsonValue::Null) => TypedValue::Null,
+            (ColumnType::Boolean, JsonValue::Bool(b)) => TypedValue::Bool(*b),
+            (ColumnType::Boolean, JsonValue::String(s)) => match s.parse() {
+                Ok(b) => TypedValue::Bool(b),
+                Err(_) => text(),
+            },
+            (
+                ColumnType::Integer
+                | ColumnType::BigInt
+                | ColumnType::Decimal
+                | ColumnType::Int256
+                | ColumnType::UInt256,
+                _,
+            ) => match json_i64(value) {
+                Some(n) => TypedValue::Int(n),
+                None => text(),
+            },
+            (ColumnType::Double, JsonValue::Number(n)) => match n.as_f64() {
+                Some(n) => TypedValue::Double(n),
+                None => text(),
+            },
+            (ColumnType::Double, JsonValue::String(s)) => match s.parse() {
+                Ok(n) => TypedValue::Double(n),
+                Err(_) => text(),
+            },
+            (ColumnType::Varbinary, JsonValue::String(s)) => match decode_hex(s) {
+                Some(bytes) => TypedValue::Bytes(bytes),
+                None => text(),
+            },
+            (
+                ColumnType::Date | ColumnType::Timestamp | ColumnType::TimestampTz,
+                JsonValue::String(s),
+            ) => TypedValue::Timestamp(s.clone()),
+            _ => text(),
+        }
+    }
+}
+
+// Integers may be returned as JSON numbers or, when too large for a double, as strings.
+fn json_i64(value: &JsonValue) -> Option<i64> {
+    match value {
+        JsonValue::Number(n) => n.as_i64(),
+        JsonValue::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Decodes a `0x`-prefixed hex string, as Dune returns `varbinary` values.
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// DDL GENERATION
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SqlDialect {
+    Postgres,
+    Sqlite,
+}
+
+impl SqlDialect {
+    pub fn column_type<'a>(&self, column_type: &'a ColumnType) -> &'a str {
+        match self {
+            SqlDialect::Postgres => column_type.postgres_type(),
+            SqlDialect::Sqlite => column_type.sqlite_type(),
+        }
+    }
+}
+
+/// Builds a `CREATE TABLE` statement from the result's `column_names` and `column_types`.
+pub fn create_table_ddl(
+    table: &str,
+    column_names: &[String],
+    column_types: &[String],
+    dialect: SqlDialect,
+) -> String {
+    let columns: Vec<String> = column_names
+        .iter()
+        .zip(column_types)
+        .map(|(name, column_type)| {
+            format!(
+                "    {} {}",
+                quote_identifier(name),
+                dialect.column_type(&ColumnType::from(column_type.as_str()))
+            )
+        })
+        .collect();
+
+    format!(
+        "CREATE TABLE {} (\n{}\n);",
+        quote_identifier(table),
+        columns.join(",\n")
+    )
+}
+
+/// Quotes the identifier, e.g. a column name, which both Postgres and SQLite accept.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_column_types() {
+        assert_eq!(ColumnType::from("varbinary"), ColumnType::Varbinary);
+        assert_eq!(ColumnType::from("decimal(38,0)"), ColumnType::Decimal);
+        assert_eq!(ColumnType::from("timestamp(3)"), ColumnType::Timestamp);
+        assert_eq!(
+            ColumnType::from("timestamp(3) with time zone"),
+            ColumnType::TimestampTz
+        );
+        assert_eq!(ColumnType::from("array(varchar)"), ColumnType::Nested);
+        assert_eq!(
+            ColumnType::from("ipaddress"),
+            ColumnType::Unknown("ipaddress".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let cases = [
+            (
+                "varbinary",
+                json!("0x00ff"),
+                TypedValue::Bytes(vec![0x00, 0xff]),
+            ),
+            ("varbinary", json!("0x0"), TypedValue::Text("0x0".into())),
+            ("bigint", json!(42), TypedValue::Int(42)),
+            (
+                "uint256",
+                json!("1000000000000000000"),
+                TypedValue::Int(1_000_000_000_000_000_000),
+            ),
+            (
+                "uint256",
+                json!("100000000000000000000"),
+                TypedValue::Text("100000000000000000000".into()),
+            ),
+            ("double", json!(1.5), TypedValue::Double(1.5)),
+            ("boolean", json!(true), TypedValue::Bool(true)),
+            (
+                "timestamp(3) with time zone",
+                json!("2024-08-23 12:46:55.606 UTC"),
+                TypedValue::Timestamp("2024-08-23 12:46:55.606 UTC".into()),
+            ),
+            ("varchar", json!("abc"), TypedValue::Text("abc".into())),
+            (
+                "array(integer)",
+                json!([1, 2]),
+                TypedValue::Text("[1,2]".into()),
+            ),
+            ("double", JsonValue::Null, TypedValue::Null),
+        ];
+        for (column_type, value, expected) in cases {
+            assert_eq!(
+                TypedValue::from_json(&value, &ColumnType::from(column_type)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_create_table_ddl() {
+        let column_names = vec!["address".to_string(), "balance".to_string()];
+        let column_types = vec!["varbinary".to_string(), "double".to_string()];
+
+        assert_eq!(
+            create_table_ddl(
+                "balances",
+                &column_names,
+                &column_types,
+                SqlDialect::Postgres
+            ),
+            "CREATE TABLE \"balances\" (\n    \"address\" BYTEA,\n    \"balance\" DOUBLE PRECISION\n);"
+        );
+        assert_eq!(
+            create_table_ddl("balances", &column_names, &column_types, SqlDialect::Sqlite),
+            "CREATE TABLE \"balances\" (\n    \"address\" BLOB,\n    \"balance\" REAL\n);"
+        );
+    }
+}
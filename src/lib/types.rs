@@ -1,50 +1,64 @@
 #![allow(dead_code)]
 
+use super::schema::{ColumnType, TypedValue};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
 
 // QUERY PARAMS
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum EngineSize {
+    #[value(alias = "l")]
     Large,
+    #[value(alias = "m")]
     Medium,
 }
 
-impl Serialize for EngineSize {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+impl EngineSize {
+    pub fn as_str(&self) -> &str {
         match self {
-            EngineSize::Large => serializer.serialize_str("large"),
-            EngineSize::Medium => serializer.serialize_str("medium"),
+            EngineSize::Large => "large",
+            EngineSize::Medium => "medium",
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Blockchains indexed by Dune, named as in Dune's tables (e.g. `avalanche_c.transactions`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
 pub enum Blockchain {
     Ethereum,
     Arbitrum,
+    /// Arbitrum Nova.
+    #[serde(rename = "nova")]
+    #[value(name = "nova")]
+    ArbitrumNova,
     Optimism,
     Base,
     Polygon,
-}
-
-impl Serialize for Blockchain {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Blockchain::Ethereum => serializer.serialize_str("ethereum"),
-            Blockchain::Arbitrum => serializer.serialize_str("arbitrum"),
-            Blockchain::Optimism => serializer.serialize_str("optimism"),
-            Blockchain::Base => serializer.serialize_str("base"),
-            Blockchain::Polygon => serializer.serialize_str("polygon"),
-        }
-    }
+    /// Polygon zkEVM.
+    #[serde(rename = "zkevm")]
+    #[value(name = "zkevm")]
+    PolygonZkevm,
+    AvalancheC,
+    Bnb,
+    Gnosis,
+    Fantom,
+    Celo,
+    Scroll,
+    Zksync,
+    Linea,
+    Blast,
+    Zora,
+    Mantle,
+    Sei,
+    Ronin,
+    Solana,
 }
 
 impl Blockchain {
@@ -52,13 +66,45 @@ impl Blockchain {
         match self {
             Blockchain::Ethereum => "ethereum",
             Blockchain::Arbitrum => "arbitrum",
+            Blockchain::ArbitrumNova => "nova",
             Blockchain::Optimism => "optimism",
             Blockchain::Base => "base",
             Blockchain::Polygon => "polygon",
+            Blockchain::PolygonZkevm => "zkevm",
+            Blockchain::AvalancheC => "avalanche_c",
+            Blockchain::Bnb => "bnb",
+            Blockchain::Gnosis => "gnosis",
+            Blockchain::Fantom => "fantom",
+            Blockchain::Celo => "celo",
+            Blockchain::Scroll => "scroll",
+            Blockchain::Zksync => "zksync",
+            Blockchain::Linea => "linea",
+            Blockchain::Blast => "blast",
+            Blockchain::Zora => "zora",
+            Blockchain::Mantle => "mantle",
+            Blockchain::Sei => "sei",
+            Blockchain::Ronin => "ronin",
+            Blockchain::Solana => "solana",
         }
     }
 }
 
+impl std::str::FromStr for Blockchain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Blockchain as ValueEnum>::from_str(s, true)
+    }
+}
+
+// API ERRORS
+
+/// Error envelope returned by the Dune API on non-success responses.
+#[derive(Debug, Deserialize)]
+pub struct ApiErrorResponse {
+    pub error: String,
+}
+
 // POST: EXECUTE QUERY
 
 #[derive(Debug, Serialize)]
@@ -68,13 +114,106 @@ pub struct ExecuteQueryParams {
     pub params: Option<JsonValue>,
 }
 
-#[derive(Debug, Deserialize)]
+// POST: EXECUTE SQL
+
+/// Raw SQL to execute, without a saved query.
+#[derive(Debug, Serialize)]
+pub struct ExecuteSqlParams<'a> {
+    pub sql: &'a str,
+    pub performance: EngineSize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ExecuteQueryResponse {
     pub execution_id: String,
-    #[serde(rename = "state", deserialize_with = "deserialize_status")]
+    #[serde(rename = "state")]
     pub status: ExecutionStatus,
 }
 
+// POST: CANCEL EXECUTION
+#[derive(Debug, Deserialize)]
+pub struct CancelExecutionResponse {
+    pub success: bool,
+}
+
+// GET: QUERY
+
+/// Metadata of a saved query.
+#[derive(Debug, Deserialize)]
+pub struct QueryMetadata {
+    pub query_id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub parameters: Vec<QueryParameter>,
+    pub is_private: bool,
+}
+
+// GET: LIST QUERIES
+
+/// Page of the queries owned by the user (or team) of the API key.
+#[derive(Debug, Deserialize)]
+pub struct ListQueriesResponse {
+    #[serde(default)]
+    pub queries: Vec<QuerySummary>,
+    /// Total number of queries, across all the pages.
+    #[serde(default)]
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuerySummary {
+    #[serde(alias = "query_id")]
+    pub id: u64,
+    pub name: String,
+}
+
+/// A parameter of a saved query, with its default value.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QueryParameter {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub param_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<JsonValue>,
+}
+
+// POST: CREATE QUERY
+
+#[derive(Debug, Serialize)]
+pub struct CreateQueryParams {
+    pub name: String,
+    pub query_sql: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<QueryParameter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub is_private: bool,
+}
+
+// PATCH: UPDATE QUERY
+
+/// Changes to a saved query, the fields left to `None` being kept as they are.
+#[derive(Debug, Default, Serialize)]
+pub struct UpdateQueryParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_sql: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Vec<QueryParameter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryCreateResponse {
+    pub query_id: u64,
+}
+
 // GET: MATERIALIZED VIEW DATA
 #[derive(Debug, Deserialize)]
 pub struct MaterializedViewResponse {
@@ -84,27 +223,128 @@ pub struct MaterializedViewResponse {
     pub last_execution_ids: Vec<String>,
 }
 
-// GET: QUERY EXECUTION STATE
+// GET: USAGE
+
+/// Credit and storage usage of the account owning the API key.
+/// All the fields are optional, since the usage reported depends on the plan.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AccountInfo {
+    #[serde(alias = "billingPeriods")]
+    pub billing_periods: Vec<BillingPeriod>,
+    #[serde(alias = "bytesUsed")]
+    pub bytes_used: Option<u64>,
+    #[serde(alias = "bytesAllowed")]
+    pub bytes_allowed: Option<u64>,
+    #[serde(alias = "privateQueries")]
+    pub private_queries: Option<u64>,
+    #[serde(alias = "privateDashboards")]
+    pub private_dashboards: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BillingPeriod {
+    #[serde(alias = "startDate")]
+    pub start_date: String,
+    #[serde(alias = "endDate")]
+    pub end_date: String,
+    #[serde(alias = "creditsUsed")]
+    pub credits_used: f64,
+    #[serde(alias = "creditsIncluded")]
+    pub credits_included: f64,
+}
+
+impl BillingPeriod {
+    pub fn credits_remaining(&self) -> f64 {
+        (self.credits_included - self.credits_used).max(0.0)
+    }
+}
+
+// POST: REFRESH MATERIALIZED VIEW
+
+#[derive(Debug, Serialize)]
+pub struct RefreshMaterializedViewParams {
+    pub performance: EngineSize,
+}
+
 #[derive(Debug, Deserialize)]
+pub struct RefreshMaterializedViewResponse {
+    pub execution_id: String,
+    #[serde(default)]
+    pub sql_id: Option<String>,
+}
+
+// POST: UPLOAD CSV
+
+#[derive(Debug, Serialize)]
+pub struct UploadCsvParams {
+    pub table_name: String,
+    pub data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub is_private: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadCsvResponse {
+    pub success: bool,
+    pub table_name: Option<String>,
+    pub full_name: Option<String>,
+    pub example_query: Option<String>,
+}
+
+// GET: QUERY EXECUTION STATE
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ExecutionStatusResponse {
     pub execution_id: String,
     pub query_id: u64,
     pub is_execution_finished: bool,
     pub result_metadata: Option<StatusResultMetadata>,
-    #[serde(rename = "state", deserialize_with = "deserialize_status")]
+    #[serde(rename = "state")]
     pub status: ExecutionStatus,
+    #[serde(default)]
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// Missing while the execution is pending.
+    #[serde(default)]
+    pub execution_started_at: Option<DateTime<Utc>>,
+    /// Missing until the execution has finished.
+    #[serde(default)]
+    pub execution_ended_at: Option<DateTime<Utc>>,
+    /// Why the execution failed, if it did.
+    #[serde(default)]
+    pub error: Option<ExecutionError>,
+    /// Position of a pending execution in the queue, if reported.
+    #[serde(default)]
+    pub queue_position: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Why an execution failed, e.g. a syntax error or the query running out of memory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecutionError {
+    #[serde(rename = "type", default)]
+    pub error_type: Option<String>,
+    pub message: String,
+    /// Extra details, e.g. the line and column of a syntax error.
+    #[serde(default)]
+    pub metadata: Option<JsonValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct StatusResultMetadata {
     pub column_names: Vec<String>,
     pub column_types: Vec<String>,
     pub datapoint_count: u64,
     pub total_row_count: u64,
+    pub result_set_bytes: Option<u64>,
+    pub total_result_set_bytes: Option<u64>,
+    /// Time spent queued before the execution started.
+    pub pending_time_millis: Option<u64>,
+    pub execution_time_millis: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-#[serde(rename_all = "UPPERCASE")]
+/// State of an execution, e.g. `QUERY_STATE_COMPLETED` in the API's responses.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExecutionStatus {
     QueryStatePending,
     QueryStateExecuting,
@@ -115,31 +355,100 @@ pub enum ExecutionStatus {
     QueryStateCompletedPartial,
 }
 
-// Custom deserializer for ExecutionStatus
-fn deserialize_status<'de, D>(deserializer: D) -> Result<ExecutionStatus, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: &str = Deserialize::deserialize(deserializer)?;
-    match s {
-        "QUERY_STATE_PENDING" => Ok(ExecutionStatus::QueryStatePending),
-        "QUERY_STATE_EXECUTING" => Ok(ExecutionStatus::QueryStateExecuting),
-        "QUERY_STATE_FAILED" => Ok(ExecutionStatus::QueryStateFailed),
-        "QUERY_STATE_COMPLETED" => Ok(ExecutionStatus::QueryStateCompleted),
-        "QUERY_STATE_CANCELLED" => Ok(ExecutionStatus::QueryStateCancelled),
-        "QUERY_STATE_EXPIRED" => Ok(ExecutionStatus::QueryStateExpired),
-        "QUERY_STATE_COMPLETED_PARTIAL" => Ok(ExecutionStatus::QueryStateCompletedPartial),
-        _ => Err(serde::de::Error::custom(format!("Invalid variant: {}", s))),
+impl ExecutionStatus {
+    const ALL: [ExecutionStatus; 7] = [
+        ExecutionStatus::QueryStatePending,
+        ExecutionStatus::QueryStateExecuting,
+        ExecutionStatus::QueryStateFailed,
+        ExecutionStatus::QueryStateCompleted,
+        ExecutionStatus::QueryStateCancelled,
+        ExecutionStatus::QueryStateExpired,
+        ExecutionStatus::QueryStateCompletedPartial,
+    ];
+
+    /// The state as returned by the API, e.g. `QUERY_STATE_COMPLETED`.
+    pub fn to_api_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::QueryStatePending => "QUERY_STATE_PENDING",
+            ExecutionStatus::QueryStateExecuting => "QUERY_STATE_EXECUTING",
+            ExecutionStatus::QueryStateFailed => "QUERY_STATE_FAILED",
+            ExecutionStatus::QueryStateCompleted => "QUERY_STATE_COMPLETED",
+            ExecutionStatus::QueryStateCancelled => "QUERY_STATE_CANCELLED",
+            ExecutionStatus::QueryStateExpired => "QUERY_STATE_EXPIRED",
+            ExecutionStatus::QueryStateCompletedPartial => "QUERY_STATE_COMPLETED_PARTIAL",
+        }
+    }
+
+    /// Whether the execution is over, successfully or not, so that its status won't change.
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            ExecutionStatus::QueryStatePending | ExecutionStatus::QueryStateExecuting => false,
+            ExecutionStatus::QueryStateFailed
+            | ExecutionStatus::QueryStateCompleted
+            | ExecutionStatus::QueryStateCancelled
+            | ExecutionStatus::QueryStateExpired
+            | ExecutionStatus::QueryStateCompletedPartial => true,
+        }
+    }
+
+    /// Whether the execution completed with results that can be retrieved, including
+    /// partial ones.
+    pub fn is_success(&self) -> bool {
+        match self {
+            ExecutionStatus::QueryStateCompleted | ExecutionStatus::QueryStateCompletedPartial => {
+                true
+            }
+            ExecutionStatus::QueryStatePending
+            | ExecutionStatus::QueryStateExecuting
+            | ExecutionStatus::QueryStateFailed
+            | ExecutionStatus::QueryStateCancelled
+            | ExecutionStatus::QueryStateExpired => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ExecutionStatus {
+    type Err = String;
+
+    /// Parses a state as returned by the API, e.g. `QUERY_STATE_COMPLETED`. On the command
+    /// line, the state can be given without its prefix and in lowercase, e.g. `completed`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_uppercase();
+        let api_str = match s.starts_with("QUERY_STATE_") {
+            true => s,
+            false => format!("QUERY_STATE_{}", s),
+        };
+        ExecutionStatus::ALL
+            .into_iter()
+            .find(|status| status.to_api_str() == api_str)
+            .ok_or_else(|| format!("unknown execution state: {}", api_str))
+    }
+}
+
+impl Serialize for ExecutionStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_api_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ExecutionStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
 // GET: QUERY EXECUTION RESULTS
 
-// Filters are supposed to have the correct format: `<column_name> <operator> <value>`
+// Filters have the format `<column_name> <operator> <value>`
 // for example, `block_time >= '2024-09-01 00:00:00'`
-//
-// TODO: create enum for operators and autogenerate the filter strings
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct QueryResultsFilter(Vec<String>);
 
 impl QueryResultsFilter {
@@ -147,6 +456,12 @@ impl QueryResultsFilter {
         QueryResultsFilter(Vec::new())
     }
 
+    /// Adds a typed filter, rendered with its value correctly quoted.
+    pub fn add_typed_filter(self, filter: Filter) -> Self {
+        self.add_filter(filter.to_filter_string())
+    }
+
+    /// Adds a raw filter expression, which is supposed to have the correct format.
     pub fn add_filter(self, filter: String) -> Self {
         let mut new = QueryResultsFilter(self.0);
         new.0.push(filter);
@@ -162,6 +477,198 @@ impl QueryResultsFilter {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    In,
+}
+
+impl FilterOp {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FilterOp::Eq => "=",
+            FilterOp::Ne => "!=",
+            FilterOp::Gt => ">",
+            FilterOp::Gte => ">=",
+            FilterOp::Lt => "<",
+            FilterOp::Lte => "<=",
+            FilterOp::Like => "LIKE",
+            FilterOp::In => "IN",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    /// Quoted when rendered, e.g. `'2024-09-01'`.
+    String(String),
+    Integer(i128),
+    Float(f64),
+    /// Values of an `IN` filter.
+    List(Vec<FilterValue>),
+}
+
+impl FilterValue {
+    fn render(&self) -> String {
+        match self {
+            // single quotes are escaped by doubling them
+            FilterValue::String(s) => format!("'{}'", s.replace('\'', "''")),
+            FilterValue::Integer(n) => n.to_string(),
+            FilterValue::Float(n) => n.to_string(),
+            FilterValue::List(values) => {
+                let values: Vec<String> = values.iter().map(FilterValue::render).collect();
+                format!("({})", values.join(", "))
+            }
+        }
+    }
+}
+
+impl From<&str> for FilterValue {
+    fn from(value: &str) -> Self {
+        FilterValue::String(value.to_string())
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::String(value)
+    }
+}
+
+impl From<i64> for FilterValue {
+    fn from(value: i64) -> Self {
+        FilterValue::Integer(value.into())
+    }
+}
+
+impl From<u64> for FilterValue {
+    fn from(value: u64) -> Self {
+        FilterValue::Integer(value.into())
+    }
+}
+
+impl From<f64> for FilterValue {
+    fn from(value: f64) -> Self {
+        FilterValue::Float(value)
+    }
+}
+
+impl<T: Into<FilterValue>> From<Vec<T>> for FilterValue {
+    fn from(values: Vec<T>) -> Self {
+        FilterValue::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Typed filter on a column of the results, e.g. `Filter::new("value", FilterOp::Gt, 0)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: String,
+    pub op: FilterOp,
+    pub value: FilterValue,
+}
+
+impl Filter {
+    pub fn new(column: &str, op: FilterOp, value: impl Into<FilterValue>) -> Self {
+        Filter {
+            column: column.to_string(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    /// Renders the filter as `<column> <operator> <value>`.
+    pub fn to_filter_string(&self) -> String {
+        let value = match (&self.op, &self.value) {
+            // `IN` always takes a parenthesized list, even with a single value
+            (FilterOp::In, FilterValue::List(_)) => self.value.render(),
+            (FilterOp::In, value) => format!("({})", value.render()),
+            (_, value) => value.render(),
+        };
+        format!("{} {} {}", self.column, self.op.as_str(), value)
+    }
+}
+
+/// Identifier of the results to retrieve: those of the latest execution of a query, or
+/// those of a specific execution.
+///
+/// Query IDs are numbers, while execution IDs are ULIDs, e.g. `01J5ZMD33P6J413G1KQM6QTE4S`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultId {
+    Query(u64),
+    Execution(String),
+}
+
+impl ResultId {
+    /// Whether the string is a ULID: 26 characters of Crockford's base32, the first of which
+    /// is at most `7` so that the timestamp fits into 48 bits.
+    pub fn is_execution_id(s: &str) -> bool {
+        s.len() == 26
+            && s.starts_with(|c: char| ('0'..='7').contains(&c))
+            && s.chars().all(|c| {
+                c.is_ascii_digit()
+                    || (c.is_ascii_alphabetic()
+                        && !matches!(c.to_ascii_uppercase(), 'I' | 'L' | 'O' | 'U'))
+            })
+    }
+}
+
+impl std::str::FromStr for ResultId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(query_id) = s.parse::<u64>() {
+            return Ok(ResultId::Query(query_id));
+        }
+        if ResultId::is_execution_id(s) {
+            return Ok(ResultId::Execution(s.to_string()));
+        }
+        Err(format!(
+            "'{}' is neither a query ID (a number) nor an execution ID (a 26-character ULID, \
+            e.g. 01J5ZMD33P6J413G1KQM6QTE4S)",
+            s
+        ))
+    }
+}
+
+impl std::fmt::Display for ResultId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultId::Query(query_id) => write!(f, "{}", query_id),
+            ResultId::Execution(execution_id) => write!(f, "{}", execution_id),
+        }
+    }
+}
+
+/// Window and columns of the results to retrieve, on top of the filters.
+#[derive(Debug, Clone, Default)]
+pub struct ResultsOptions {
+    /// Offset of the first row to retrieve.
+    pub offset: u64,
+    /// Rows per page. Defaults to the count of rows to peak at when peaking, 1000 otherwise.
+    pub limit: Option<u64>,
+    /// Columns to retrieve, all of them if `None`.
+    pub columns: Option<Vec<String>>,
+    /// Sort order of the rows, e.g. `block_time desc`.
+    pub sort_by: Option<String>,
+    /// Parameters of a parameterized query, selecting the latest execution with those
+    /// parameters. Only applies to the results of a query, not of an execution.
+    pub params: Option<JsonValue>,
+    /// Whether to lift the maximum datapoints per request, allowing larger pages at the cost
+    /// of more credits per request.
+    pub ignore_datapoint_limit: bool,
+    /// Whether to fetch only the first page, of `limit` rows, instead of all of them.
+    pub single_page: bool,
+    /// Maximum number of rows to retrieve across all the pages, all of them if `None`.
+    pub max_rows: Option<u64>,
+}
+
 #[derive(Debug, Serialize)]
 pub enum ResultsParams<'a> {
     Query(QueryResultsParams),
@@ -176,6 +683,7 @@ impl<'a> ResultsParams<'a> {
         limit: u64,
         columns: Option<Vec<String>>,
         filters: QueryResultsFilter,
+        sort_by: Option<String>,
     ) -> Self {
         ResultsParams::Query(QueryResultsParams {
             query_id: id,
@@ -184,6 +692,8 @@ impl<'a> ResultsParams<'a> {
             offset,
             limit,
             filters: filters.to_option_string(),
+            sort_by,
+            query_parameters: BTreeMap::new(),
         })
     }
 
@@ -194,6 +704,7 @@ impl<'a> ResultsParams<'a> {
         limit: u64,
         columns: Option<Vec<String>>,
         filters: QueryResultsFilter,
+        sort_by: Option<String>,
     ) -> Self {
         ResultsParams::Execution(ExecutionResultsParams {
             execution_id: id,
@@ -202,6 +713,7 @@ impl<'a> ResultsParams<'a> {
             offset,
             limit,
             filters: filters.to_option_string(),
+            sort_by,
         })
     }
     pub fn update_offset(&mut self, new_offset: u64) {
@@ -222,6 +734,40 @@ impl<'a> ResultsParams<'a> {
         }
     }
 
+    pub fn update_limit(&mut self, new_limit: u64) {
+        match self {
+            ResultsParams::Query(ref mut query_params) => {
+                query_params.limit = new_limit;
+            }
+            ResultsParams::Execution(ref mut execution_params) => {
+                execution_params.limit = new_limit;
+            }
+        }
+    }
+
+    pub fn get_limit(&self) -> u64 {
+        match self {
+            ResultsParams::Query(query_params) => query_params.limit,
+            ResultsParams::Execution(execution_params) => execution_params.limit,
+        }
+    }
+
+    /// Sets the query parameters, sent as `params.<name>=<value>`. No-op for execution results.
+    pub fn set_query_parameters(&mut self, params: &JsonValue) {
+        if let (ResultsParams::Query(query_params), Some(params)) = (self, params.as_object()) {
+            query_params.query_parameters = params
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        JsonValue::String(s) => s.clone(),
+                        value => value.to_string(),
+                    };
+                    (format!("params.{}", name), value)
+                })
+                .collect();
+        }
+    }
+
     pub fn url_encode(&self) -> Result<String, serde_urlencoded::ser::Error> {
         match self {
             ResultsParams::Query(query_params) => serde_urlencoded::to_string(query_params),
@@ -235,23 +781,42 @@ impl<'a> ResultsParams<'a> {
 // to get the results of a specific query execution
 #[derive(Debug, Serialize)]
 pub struct ExecutionResultsParams<'a> {
+    #[serde(serialize_with = "serialize_columns")]
     pub columns: Option<Vec<String>>,
     pub execution_id: &'a str,
     pub offset: u64,
     pub limit: u64,
     pub ignore_max_datapoints_per_request: bool,
     pub filters: Option<String>,
+    /// e.g. `block_time desc`
+    pub sort_by: Option<String>,
 }
 
 // to get the results of the latest execution of a query
 #[derive(Debug, Serialize)]
 pub struct QueryResultsParams {
+    #[serde(serialize_with = "serialize_columns")]
     pub columns: Option<Vec<String>>,
     pub query_id: u64,
     pub offset: u64,
     pub limit: u64,
     pub ignore_max_datapoints_per_request: bool,
     pub filters: Option<String>,
+    /// e.g. `block_time desc`
+    pub sort_by: Option<String>,
+    #[serde(flatten)]
+    pub query_parameters: BTreeMap<String, String>,
+}
+
+// The API expects the columns as a single comma-separated value
+fn serialize_columns<S>(columns: &Option<Vec<String>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match columns {
+        Some(columns) => serializer.serialize_some(&columns.join(",")),
+        None => serializer.serialize_none(),
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -261,15 +826,78 @@ pub struct QueryResultsResponse {
     pub is_execution_finished: bool,
     pub next_offset: Option<u64>,
     pub query_id: u64,
+    /// Missing once the results have expired.
+    #[serde(default)]
     pub result: QueryResult,
+    /// Why the execution failed, if it did.
+    #[serde(default)]
+    pub error: Option<ExecutionError>,
+}
+
+impl QueryResultsResponse {
+    /// The parsed state of the execution, or `None` if the API returned an unknown one.
+    pub fn status(&self) -> Option<ExecutionStatus> {
+        self.state.parse().ok()
+    }
+
+    /// Whether the execution completed partially (e.g. it ran out of memory), so only some of
+    /// the rows are available.
+    pub fn is_partial(&self) -> bool {
+        self.state == "QUERY_STATE_COMPLETED_PARTIAL"
+    }
+
+    /// Whether the results of the execution have expired.
+    pub fn is_expired(&self) -> bool {
+        self.state == "QUERY_STATE_EXPIRED"
+    }
 }
 
 #[derive(Debug, Deserialize, Default)]
 pub struct QueryResult {
+    // populated from the response envelope, not part of the `result` object
+    #[serde(skip)]
+    pub query_id: u64,
+    #[serde(skip)]
+    pub execution_id: String,
+    #[serde(skip)]
+    pub state: String,
+    /// Whether the execution completed partially, so only some of the rows are available.
+    #[serde(skip)]
+    pub is_partial: bool,
+    /// Offset of the first row not retrieved (e.g. when fetching a single page), from which
+    /// the retrieval can be resumed. `None` once all the rows have been retrieved.
+    #[serde(skip)]
+    pub next_offset: Option<u64>,
+    /// Number of pages fetched to retrieve the rows. The datapoints of all these pages add up
+    /// to `metadata.datapoint_count`.
+    #[serde(skip)]
+    pub page_count: u64,
     pub metadata: QueryResultMetadata,
     pub rows: Vec<JsonValue>,
 }
 
+impl QueryResult {
+    /// Returns the rows with their cells coerced to the types of their columns, in the order
+    /// of `metadata.column_names`. Missing cells are `Null`.
+    pub fn typed_rows(&self) -> impl Iterator<Item = Vec<TypedValue>> + '_ {
+        let column_types: Vec<ColumnType> = (0..self.metadata.column_names.len())
+            .map(|i| ColumnType::from(self.metadata.column_types.get(i).map_or("", String::as_str)))
+            .collect();
+
+        self.rows.iter().map(move |row| {
+            self.metadata
+                .column_names
+                .iter()
+                .zip(&column_types)
+                .map(|(name, column_type)| match row.get(name) {
+                    Some(value) => TypedValue::from_json(value, column_type),
+                    None => TypedValue::Null,
+                })
+                .collect()
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, Default)]
 pub struct QueryResultMetadata {
     pub column_names: Vec<String>,
@@ -279,10 +907,112 @@ pub struct QueryResultMetadata {
     pub row_count: u128,
 }
 
+impl QueryResultMetadata {
+    /// Keeps only the given columns, in the given order, returning those that don't exist.
+    pub fn project(&mut self, columns: &[String]) -> Vec<String> {
+        let (mut names, mut types, mut missing) = (Vec::new(), Vec::new(), Vec::new());
+        for column in columns {
+            match self.column_names.iter().position(|name| name == column) {
+                Some(index) => {
+                    names.push(self.column_names[index].clone());
+                    types.push(self.column_types.get(index).cloned().unwrap_or_default());
+                }
+                None => missing.push(column.clone()),
+            }
+        }
+        self.column_names = names;
+        self.column_types = types;
+        missing
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_blockchain_names() {
+        for chain in Blockchain::value_variants() {
+            assert_eq!(chain.as_str().parse::<Blockchain>().unwrap(), *chain);
+            assert_eq!(
+                serde_json::to_string(chain).unwrap(),
+                format!("\"{}\"", chain.as_str())
+            );
+        }
+        assert_eq!(
+            "AVALANCHE_C".parse::<Blockchain>().unwrap(),
+            Blockchain::AvalancheC
+        );
+        assert!("bitcoin_cash".parse::<Blockchain>().is_err());
+    }
+
+    #[test]
+    fn test_account_info() {
+        let response: &str = r#"
+            {
+                "billing_periods": [
+                    {
+                        "start_date": "2026-10-01",
+                        "end_date": "2026-10-31",
+                        "credits_used": 1250.5,
+                        "credits_included": 4000
+                    }
+                ],
+                "bytes_used": 1024,
+                "bytes_allowed": 1048576
+            }
+            "#;
+
+        let account: AccountInfo = serde_json::from_str(response).unwrap();
+        assert_eq!(account.billing_periods.len(), 1);
+        assert_eq!(account.billing_periods[0].credits_remaining(), 2749.5);
+        assert_eq!(account.bytes_allowed, Some(1048576));
+        assert!(account.private_queries.is_none());
+    }
+
+    #[test]
+    fn test_parse_result_id() {
+        assert_eq!("3998990".parse(), Ok(ResultId::Query(3998990)));
+        assert_eq!(
+            "01J5ZMD33P6J413G1KQM6QTE4S".parse(),
+            Ok(ResultId::Execution(
+                "01J5ZMD33P6J413G1KQM6QTE4S".to_string()
+            ))
+        );
+        assert!("01J5ZMD33P6J413G1KQM6QTE4".parse::<ResultId>().is_err());
+        assert!("01J5ZMD33P6J413G1KQM6QTEIS".parse::<ResultId>().is_err());
+        assert!("my-query".parse::<ResultId>().is_err());
+    }
+
+    #[test]
+    fn test_query_metadata() {
+        let response: &str = r#"
+            {
+                "query_id": 3998990,
+                "name": "LP positions",
+                "description": "",
+                "tags": ["dex", "lp"],
+                "version": 4,
+                "parameters": [
+                    {"key": "min_lp_value_usd", "value": "1000", "type": "number"}
+                ],
+                "query_engine": "v2 Dune SQL",
+                "is_private": false,
+                "is_archived": false
+            }
+            "#;
+
+        let metadata: QueryMetadata = serde_json::from_str(response).unwrap();
+        assert_eq!(metadata.query_id, 3998990);
+        assert_eq!(metadata.name, "LP positions");
+        assert_eq!(metadata.tags, vec!["dex", "lp"]);
+        assert!(!metadata.is_private);
+        assert_eq!(metadata.parameters.len(), 1);
+        assert_eq!(metadata.parameters[0].key, "min_lp_value_usd");
+        assert_eq!(metadata.parameters[0].param_type, "number");
+        assert_eq!(metadata.parameters[0].value, Some(JsonValue::from("1000")));
+    }
+
     #[test]
     fn test_finished_execution_status_response() {
         let response: &str = r#"
@@ -316,6 +1046,18 @@ mod tests {
         assert_eq!(response.query_id, 4011227);
         assert!(response.is_execution_finished);
         assert_eq!(response.status, ExecutionStatus::QueryStateCompleted);
+        assert_eq!(
+            response.submitted_at.unwrap().to_rfc3339(),
+            "2024-08-23T12:46:55.606607+00:00"
+        );
+        // nanosecond precision is kept
+        assert_eq!(
+            response
+                .execution_ended_at
+                .unwrap()
+                .timestamp_subsec_nanos(),
+            370482549
+        );
 
         let metadata = response.result_metadata.unwrap();
         assert_eq!(
@@ -325,6 +1067,148 @@ mod tests {
         assert_eq!(metadata.column_types, vec!["varbinary", "double", "double"]);
         assert_eq!(metadata.total_row_count, 1068677);
         assert_eq!(metadata.datapoint_count, 3206031);
+        assert_eq!(metadata.result_set_bytes, Some(61983266));
+        assert_eq!(metadata.pending_time_millis, Some(1614));
+        assert_eq!(metadata.execution_time_millis, Some(1122148));
+    }
+
+    #[test]
+    fn test_execution_status_is_terminal() {
+        let cases = [
+            (ExecutionStatus::QueryStatePending, false, false),
+            (ExecutionStatus::QueryStateExecuting, false, false),
+            (ExecutionStatus::QueryStateFailed, true, false),
+            (ExecutionStatus::QueryStateCompleted, true, true),
+            (ExecutionStatus::QueryStateCancelled, true, false),
+            (ExecutionStatus::QueryStateExpired, true, false),
+            (ExecutionStatus::QueryStateCompletedPartial, true, true),
+        ];
+        for (status, is_terminal, is_success) in cases {
+            assert_eq!(status.is_terminal(), is_terminal, "{:?}", status);
+            assert_eq!(status.is_success(), is_success, "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn test_parse_execution_status() {
+        for status in ExecutionStatus::ALL {
+            assert_eq!(status.to_api_str().parse(), Ok(status));
+        }
+        assert_eq!(
+            "completed_partial".parse(),
+            Ok(ExecutionStatus::QueryStateCompletedPartial)
+        );
+        assert!("QUERY_STATE_UNKNOWN".parse::<ExecutionStatus>().is_err());
+        assert!(serde_json::from_str::<ExecutionStatus>(r#""QUERY_STATE_FAILED""#).is_ok());
+        assert!(serde_json::from_str::<ExecutionStatus>(r#""QUERY_STATE_UNKNOWN""#).is_err());
+    }
+
+    // Deserializes `value`, serializes it back and checks that nothing was lost or renamed.
+    fn assert_round_trip<T: Serialize + serde::de::DeserializeOwned>(value: JsonValue) {
+        let parsed: T = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+    }
+
+    #[test]
+    fn test_execution_status_round_trip() {
+        for status in ExecutionStatus::ALL {
+            let value = serde_json::to_value(status).unwrap();
+            assert_eq!(value, JsonValue::from(status.to_api_str()));
+            assert_eq!(
+                serde_json::from_value::<ExecutionStatus>(value).unwrap(),
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn test_execute_query_response_round_trip() {
+        assert_round_trip::<ExecuteQueryResponse>(serde_json::json!({
+            "execution_id": "01J5ZV5R55K2MA1943RFX994B3",
+            "state": "QUERY_STATE_PENDING"
+        }));
+    }
+
+    #[test]
+    fn test_status_result_metadata_round_trip() {
+        assert_round_trip::<StatusResultMetadata>(serde_json::json!({
+            "column_names": ["block_time", "value"],
+            "column_types": ["timestamp with time zone", "double"],
+            "datapoint_count": 4,
+            "total_row_count": 2,
+            "result_set_bytes": 96,
+            "total_result_set_bytes": 96,
+            "pending_time_millis": 62,
+            "execution_time_millis": 1234
+        }));
+    }
+
+    #[test]
+    fn test_execution_status_response_round_trip() {
+        assert_round_trip::<ExecutionStatusResponse>(serde_json::json!({
+            "execution_id": "01J5ZV5R55K2MA1943RFX994B3",
+            "query_id": 4011227,
+            "is_execution_finished": true,
+            "result_metadata": {
+                "column_names": ["value"],
+                "column_types": ["double"],
+                "datapoint_count": 1,
+                "total_row_count": 1,
+                "result_set_bytes": null,
+                "total_result_set_bytes": null,
+                "pending_time_millis": null,
+                "execution_time_millis": 1234
+            },
+            "state": "QUERY_STATE_COMPLETED",
+            "submitted_at": "2024-08-23T14:45:15.045773Z",
+            "execution_started_at": "2024-08-23T14:45:15.107Z",
+            "execution_ended_at": "2024-08-23T14:45:16.341Z",
+            "error": null,
+            "queue_position": null
+        }));
+        assert_round_trip::<ExecutionStatusResponse>(serde_json::json!({
+            "execution_id": "01J5ZV5R55K2MA1943RFX994B3",
+            "query_id": 4011227,
+            "is_execution_finished": true,
+            "result_metadata": null,
+            "state": "QUERY_STATE_FAILED",
+            "submitted_at": "2024-08-23T14:45:15.045773Z",
+            "execution_started_at": null,
+            "execution_ended_at": null,
+            "error": {
+                "type": "FAILED_TYPE_EXECUTION_FAILED",
+                "message": "line 1:8: Column 'valu' cannot be resolved",
+                "metadata": {"line": 1, "column": 8}
+            },
+            "queue_position": null
+        }));
+    }
+
+    #[test]
+    fn test_failed_execution_status_response() {
+        let response: &str = r#"
+            {
+                "execution_id": "01J5ZV5R55K2MA1943RFX994B3",
+                "query_id": 4011227,
+                "is_execution_finished": true,
+                "state": "QUERY_STATE_FAILED",
+                "submitted_at": "2024-08-23T14:45:15.045773Z",
+                "error": {
+                    "type": "FAILED_TYPE_EXECUTION_FAILED",
+                    "message": "line 1:8: Column 'valu' cannot be resolved",
+                    "metadata": {"line": 1, "column": 8}
+                }
+            }
+            "#;
+
+        let response: ExecutionStatusResponse = serde_json::from_str(response).unwrap();
+        assert_eq!(response.status, ExecutionStatus::QueryStateFailed);
+        let error = response.error.unwrap();
+        assert_eq!(
+            error.error_type.as_deref(),
+            Some("FAILED_TYPE_EXECUTION_FAILED")
+        );
+        assert_eq!(error.message, "line 1:8: Column 'valu' cannot be resolved");
     }
 
     #[test]
@@ -347,5 +1231,108 @@ mod tests {
         assert_eq!(response.query_id, 4011227);
         assert!(!response.is_execution_finished);
         assert_eq!(response.status, ExecutionStatus::QueryStateExecuting);
+        assert!(response.execution_started_at.is_some());
+        assert!(response.execution_ended_at.is_none());
+    }
+
+    #[test]
+    fn test_project_metadata_columns() {
+        let mut metadata = QueryResultMetadata {
+            column_names: vec!["block_time".into(), "address".into(), "value".into()],
+            column_types: vec!["timestamp".into(), "varbinary".into(), "double".into()],
+            ..Default::default()
+        };
+        let missing = metadata.project(&["value".into(), "adress".into(), "block_time".into()]);
+        assert_eq!(missing, vec!["adress"]);
+        assert_eq!(metadata.column_names, vec!["value", "block_time"]);
+        assert_eq!(metadata.column_types, vec!["double", "timestamp"]);
+    }
+
+    #[test]
+    fn test_partial_results_response() {
+        let response: &str = r#"
+            {
+                "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+                "query_id": 4011227,
+                "is_execution_finished": true,
+                "state": "QUERY_STATE_COMPLETED_PARTIAL",
+                "next_offset": null,
+                "result": {
+                    "metadata": {
+                        "column_names": ["address"],
+                        "column_types": ["varbinary"],
+                        "row_count": 1,
+                        "total_row_count": 1,
+                        "datapoint_count": 1
+                    },
+                    "rows": [{"address": "0x00"}]
+                }
+            }
+            "#;
+
+        let response: QueryResultsResponse = serde_json::from_str(response).unwrap();
+        assert!(response.is_partial());
+    }
+
+    #[test]
+    fn test_expired_results_response() {
+        let response: &str = r#"
+            {
+                "execution_id": "01J5ZMD33P6J413G1KQM6QTE4S",
+                "query_id": 4011227,
+                "is_execution_finished": true,
+                "state": "QUERY_STATE_EXPIRED"
+            }
+            "#;
+
+        let response: QueryResultsResponse = serde_json::from_str(response).unwrap();
+        assert!(response.is_expired());
+        assert!(response.result.rows.is_empty());
+    }
+
+    #[test]
+    fn test_url_encode_results_params() {
+        let params = ResultsParams::new_query(
+            4011227,
+            false,
+            2000,
+            1000,
+            Some(vec!["block_time".to_string(), "value".to_string()]),
+            QueryResultsFilter::new(),
+            Some("block_time desc".to_string()),
+        );
+        assert_eq!(
+            params.url_encode().unwrap(),
+            "columns=block_time%2Cvalue&query_id=4011227&offset=2000&limit=1000\
+            &ignore_max_datapoints_per_request=false&sort_by=block_time+desc"
+        );
+    }
+
+    #[test]
+    fn test_typed_filters() {
+        let filters = QueryResultsFilter::new()
+            .add_typed_filter(Filter::new("block_time", FilterOp::Gte, "2024-09-01"))
+            .add_typed_filter(Filter::new("name", FilterOp::Like, "O'Brien%"))
+            .add_typed_filter(Filter::new("chain", FilterOp::In, vec!["ethereum", "base"]))
+            .add_typed_filter(Filter::new("value", FilterOp::In, 1u64))
+            .add_filter("amount > 0".to_string());
+
+        assert_eq!(
+            filters.to_option_string().unwrap(),
+            "block_time >= '2024-09-01' AND name LIKE 'O''Brien%' \
+            AND chain IN ('ethereum', 'base') AND value IN (1) AND amount > 0"
+        );
+    }
+
+    #[test]
+    fn test_url_encode_query_parameters() {
+        let mut params =
+            ResultsParams::new_query(1, false, 0, 10, None, QueryResultsFilter::new(), None);
+        params.set_query_parameters(&serde_json::json!({ "chain": "base", "min_value": 100 }));
+        assert_eq!(
+            params.url_encode().unwrap(),
+            "query_id=1&offset=0&limit=10&ignore_max_datapoints_per_request=false\
+            &params.chain=base&params.min_value=100"
+        );
     }
 }
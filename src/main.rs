@@ -1,24 +1,120 @@
-mod lib;
+mod config;
+mod credentials;
+mod diff;
+mod history;
+mod state;
 mod utils;
 
-use clap::{Parser, Subcommand};
+use chrono::SecondsFormat;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 use dotenv::dotenv;
-use lib::{
-    client::DuneClient,
-    types::{EngineSize, QueryResultsFilter},
+use dune_cli::{
+    client::{DuneClient, DuneError, ResultsPager, DEFAULT_BATCH_CONCURRENCY},
+    schema::{self, SqlDialect},
+    types::{
+        AccountInfo, EngineSize, ExecutionStatus, ExecutionStatusResponse, Filter, FilterOp,
+        QueryMetadata, QueryParameter, QueryResult, QueryResultsFilter, QueryResultsResponse,
+        ResultId, ResultsOptions, StatusResultMetadata, UpdateQueryParams,
+    },
 };
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use tracing::{debug, error, info};
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, level_filters::LevelFilter, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Delimiter of the CSV output, unless specified otherwise.
+const DEFAULT_CSV_DELIMITER: u8 = b';';
+
+/// Exit code for errors returned by the Dune API Client.
+const EXIT_ERROR: i32 = 1;
+/// Exit code for invalid command-line usage, as used by clap, e.g. when no API key is provided.
+const EXIT_USAGE: i32 = 2;
+/// Exit code when the account is not entitled to the requested operation (e.g. HTTP 402).
+const EXIT_PAYMENT_REQUIRED: i32 = 3;
+/// Exit code when no rows were returned with `--fail-on-empty`.
+const EXIT_EMPTY_RESULTS: i32 = 4;
+/// Exit code when interrupted with Ctrl-C, by convention 128 + the number of SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Version with the git commit and the build date, embedded by the build script.
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (commit ",
+    env!("DUNE_CLI_GIT_COMMIT"),
+    ", built ",
+    env!("DUNE_CLI_BUILD_DATE"),
+    ")"
+);
+
+/// Format of the logs written to stderr.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the level, timestamp and fields.
+    Json,
+}
 
 /// Small CLI tool for executing commands of the Dune API Client.
 #[derive(Parser, Debug)]
-#[command(about = "Small CLI tool for executing commands of the Dune API Client.")]
+#[command(
+    version,
+    long_version = LONG_VERSION,
+    about = "Small CLI tool for executing commands of the Dune API Client."
+)]
 struct Cli {
     /// The API key for authenticating with the Dune API.
     /// Can be provided via the env variable `DUNE_API_KEY`.
-    #[clap(short = 'k', long, env = "DUNE_API_KEY")]
+    /// If the `keyring` feature is enabled, the OS keyring is checked as a fallback.
+    #[clap(short = 'k', long)]
     api_key: Option<String>,
 
+    /// (Optional) Path to a file containing the API key, which keeps it out of the process
+    /// list and the shell history. Can be provided via the env variable `DUNE_API_KEY_FILE`.
+    #[clap(long, conflicts_with = "api_key")]
+    api_key_file: Option<PathBuf>,
+
+    /// (Optional) Path to the config file. Defaults to `dune-cli/config.toml` in the user's
+    /// config directory, e.g. `~/.config/dune-cli/config.toml`.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// (Optional) Profile of the config file to use, i.e. its `[profiles.<name>]` section, e.g.
+    /// to switch between the API keys of several accounts. Can be provided via the env
+    /// variable `DUNE_PROFILE`.
+    #[clap(long, env = "DUNE_PROFILE")]
+    profile: Option<String>,
+
+    /// (Optional) Only log errors.
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// (Optional) Log debug messages too.
+    /// Without `--quiet`, `--verbose` or `--trace`, the `RUST_LOG` env variable is honored.
+    #[clap(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// (Optional) Log everything, including the requests (with the API key redacted) and the
+    /// raw responses. Beware that these may contain query parameters and results.
+    #[clap(long, global = true, conflicts_with = "quiet")]
+    trace: bool,
+
+    /// (Optional) Format of the logs: "text" (default) or "json", one object per line, e.g. to
+    /// parse them in a pipeline.
+    #[clap(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// (Optional) Log the requests (URL, headers with the API key redacted, and body) instead
+    /// of sending them, e.g. to check how the parameters and filters are serialized.
+    #[clap(long, global = true)]
+    dry_run: bool,
+
     /// The subcommand to execute.
     #[command(subcommand)]
     command: Commands,
@@ -30,17 +126,105 @@ enum Commands {
     /// Execute a new query with the Dune API.
     Execute {
         /// The unique identifier of the query to execute.
+        #[clap(long, required_unless_present = "query_name")]
+        id: Option<u64>,
+
+        /// (Optional) Name of the saved query to execute, instead of its id. Must match a
+        /// single query of the user (or team) of the API key.
+        #[clap(long, conflicts_with = "id")]
+        query_name: Option<String>,
+
+        /// (Optional) Engine size to use for the query execution. Defaults to "medium".
+        #[clap(long, value_enum, ignore_case = true)]
+        engine_size: Option<EngineSize>,
+
+        /// (Optional) Query parameters in JSON format.
+        #[clap(long, value_parser = parse_params_json)]
+        params: Option<JsonValue>,
+
+        /// (Optional) Path to a JSON file with the query parameters, as an object.
+        #[clap(long, conflicts_with = "params")]
+        params_file: Option<PathBuf>,
+
+        /// (Optional) Print the execution id and state as a JSON object to stdout.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Execute raw SQL, without a saved query.
+    Sql {
+        /// The SQL to execute, e.g. "SELECT * FROM ethereum.blocks LIMIT 10".
+        #[clap(long, required_unless_present = "sql_file")]
+        query: Option<String>,
+
+        /// (Optional) Path to a file with the SQL to execute, instead of `--query`.
+        #[clap(long, conflicts_with = "query")]
+        sql_file: Option<PathBuf>,
+
+        /// (Optional) Engine size to use for the execution. Defaults to "medium".
+        #[clap(long, value_enum, ignore_case = true)]
+        engine_size: Option<EngineSize>,
+    },
+
+    /// Save a new query, with its SQL read from a file.
+    CreateQuery {
+        /// Name of the query.
+        #[clap(long)]
+        name: String,
+
+        /// Path to a file with the SQL of the query.
+        #[clap(long)]
+        sql_file: PathBuf,
+
+        /// (Optional) Parameters of the query, as a JSON array, e.g.
+        /// `[{"key": "min_value", "type": "number", "value": "1"}]`.
+        // the full path keeps clap from taking the array for repeated values
+        #[clap(long, value_parser = parse_query_parameters)]
+        parameters: Option<::std::vec::Vec<QueryParameter>>,
+
+        /// (Optional) Description of the query.
+        #[clap(long)]
+        description: Option<String>,
+
+        /// (Optional) Make the query private.
+        #[clap(long)]
+        private: bool,
+    },
+
+    /// Update a saved query. Only the given fields are changed.
+    UpdateQuery {
+        /// The unique identifier of the query to update.
         #[clap(long)]
         id: u64,
 
-        /// (Optional) Engine size to use for the query execution.
-        /// Can be either "medium" or "large". Defaults to "medium".
+        /// (Optional) Path to a file with the new SQL of the query.
         #[clap(long)]
-        engine_size: Option<String>,
+        sql_file: Option<PathBuf>,
 
-        /// (Optional) Query parameters in JSON format.
+        /// (Optional) New name of the query.
         #[clap(long)]
-        params: Option<JsonValue>,
+        name: Option<String>,
+
+        /// (Optional) New parameters of the query, as a JSON array (see `create-query`).
+        #[clap(long, value_parser = parse_query_parameters)]
+        parameters: Option<::std::vec::Vec<QueryParameter>>,
+
+        /// (Optional) New description of the query.
+        #[clap(long)]
+        description: Option<String>,
+    },
+
+    /// Execute several queries concurrently, as listed in a JSON file.
+    ExecuteBatch {
+        /// Path to a JSON array of queries, e.g.
+        /// `[{"query_id": 1234, "engine_size": "large", "params": {"min_value": 1}}]`.
+        /// `engine_size` and `params` are optional.
+        #[clap(long)]
+        file: PathBuf,
+
+        /// (Optional) Maximum number of executions submitted at once.
+        #[clap(long, default_value_t = DEFAULT_BATCH_CONCURRENCY)]
+        concurrency: usize,
     },
 
     /// Retrieve the execution status of a previously executed query.
@@ -48,34 +232,140 @@ enum Commands {
         /// The unique identifier of the execution for which to retrieve results.
         #[clap(long)]
         id: String,
+
+        /// (Optional) Print the execution status as a JSON object to stdout.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Cancel an ongoing execution.
+    Cancel {
+        /// The unique identifier of the execution to cancel.
+        #[clap(long)]
+        id: String,
+    },
+
+    /// Wait until an execution submitted elsewhere completes, then retrieve its results.
+    Wait {
+        /// The unique identifier of the execution to wait for.
+        #[clap(long)]
+        id: String,
+
+        #[command(flatten)]
+        poll: PollArgs,
+
+        #[command(flatten)]
+        results: ResultsArgs,
+    },
+
+    /// Retrieve the metadata (name, description, tags, parameters) of a saved query.
+    GetQuery {
+        /// The unique identifier of the query.
+        #[clap(long)]
+        id: u64,
     },
 
-    /// Retrieve metadata of a materialized view.
+    /// Retrieve the rows of a materialized view, i.e. the latest results of its query.
     GetMaterializedView {
         /// The unique identifier (name) of the materialized view for which to retrieve data.
         #[clap(long)]
         id: String,
+
+        #[command(flatten)]
+        results: ResultsArgs,
+    },
+
+    /// Show the credits and storage used by the account, to avoid running out mid-export.
+    Credits,
+
+    /// Refresh a materialized view.
+    RefreshView {
+        /// The unique identifier (name) of the materialized view to refresh.
+        #[clap(long)]
+        id: String,
+
+        /// (Optional) Engine size to use for the refresh. Defaults to "medium".
+        #[clap(long, value_enum, ignore_case = true)]
+        engine_size: Option<EngineSize>,
+
+        /// (Optional) Wait until the refresh completes.
+        #[clap(long)]
+        wait: bool,
+
+        #[command(flatten)]
+        poll: PollArgs,
     },
 
     /// Retrieve results for a previously executed query.
     GetResults {
         /// The unique identifier of the execution for which to retrieve results.
-        /// If a query ID is provided, the results its latest execution will be returned.
-        /// If an execution ID is provided, the results for that specific execution will be returned.
+        /// If a query ID (a number) is provided, the results its latest execution will be returned.
+        /// If an execution ID (a ULID) is provided, the results for that specific execution will be returned.
         #[clap(long)]
-        id: String,
+        id: ResultId,
+
+        /// (Optional) Filter to apply to the results, e.g. "value > 0".
+        /// Can be repeated, in which case the filters are combined with AND.
+        #[clap(short, long, action = ArgAction::Append)]
+        filter: Vec<String>,
+
+        /// (Optional) Only retrieve the rows from this date or RFC3339 datetime onwards, e.g.
+        /// "2024-09-01" (from midnight UTC) or "2024-09-01T12:00:00Z", on `--time-column`.
+        #[clap(long, value_parser = parse_since)]
+        since: Option<String>,
+
+        /// (Optional) Only retrieve the rows before this date or RFC3339 datetime, on
+        /// `--time-column`. A date alone includes the whole day.
+        #[clap(long, value_parser = parse_until)]
+        until: Option<String>,
+
+        /// (Optional) Timestamp column filtered by `--since` and `--until`.
+        #[clap(long, default_value = "block_time")]
+        time_column: String,
+
+        /// (Optional) Query parameters in JSON format, matching the latest execution with
+        /// those parameters. Only valid with a query ID.
+        #[clap(long, value_parser = parse_params_json)]
+        params: Option<JsonValue>,
+
+        /// (Optional) Resume an interrupted export from the given offset, appending the rows
+        /// to the existing CSV file without repeating the headers. The upload of `--upload-as`
+        /// would miss the rows before the offset, so they can't be combined.
+        #[clap(long, requires = "path_csv", conflicts_with_all = ["offset", "checkpoint", "page_files", "upload_as"])]
+        resume_from: Option<u64>,
+
+        #[command(flatten)]
+        results: ResultsArgs,
+    },
 
-        /// (Optional) Whether to apply a filter to the results or not.
-        #[clap(short, long)]
-        filter: Option<String>,
+    /// Retrieve only the schema (column names and types) of the results, without downloading the rows.
+    Schema {
+        /// The unique identifier of the query (latest execution) or execution.
+        #[clap(long)]
+        id: ResultId,
+
+        /// (Optional) Print a CREATE TABLE statement for the given SQL dialect instead of a table.
+        #[clap(long, value_enum)]
+        as_ddl: Option<SqlDialect>,
 
-        /// (Optional) Whether to retrieve all rows, or only the first 10 records.
-        #[clap(short, long)]
-        peak: Option<bool>,
+        /// (Optional) Table name used in the CREATE TABLE statement. Defaults to "dune_<id>".
+        #[clap(long)]
+        table: Option<String>,
+    },
 
-        /// (Optional) Path where the resulting CSV file should be saved.
+    /// Retrieve the latest result of a query without executing it again.
+    LatestResult {
+        /// The unique identifier of the query.
         #[clap(long)]
-        path_csv: Option<String>,
+        id: u64,
+
+        /// (Optional) Query parameters in JSON format, matching the latest execution with
+        /// those parameters.
+        #[clap(long, value_parser = parse_params_json)]
+        params: Option<JsonValue>,
+
+        #[command(flatten)]
+        results: ResultsArgs,
     },
 
     /// Execute a new query with the Dune API and wait until the results are ready.
@@ -84,191 +374,2296 @@ enum Commands {
         #[clap(long)]
         id: u64,
 
-        /// (Optional) Engine size to use for the query execution.
-        /// Can be either "medium" or "large". Defaults to "medium".
-        #[clap(long)]
-        engine_size: Option<String>,
+        /// (Optional) Engine size to use for the query execution. Defaults to "medium".
+        #[clap(long, value_enum, ignore_case = true)]
+        engine_size: Option<EngineSize>,
 
         /// (Optional) Query parameters in JSON format.
+        #[clap(long, value_parser = parse_params_json)]
+        params: Option<JsonValue>,
+
+        /// (Optional) Path to a JSON file with the query parameters, as an object.
+        #[clap(long, conflicts_with = "params")]
+        params_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        poll: PollArgs,
+
+        #[command(flatten)]
+        results: ResultsArgs,
+    },
+
+    /// Re-execute a query on an interval and redraw its results as a table, until Ctrl-C.
+    Watch {
+        /// The unique identifier of the query to execute.
         #[clap(long)]
+        id: u64,
+
+        /// (Optional) Seconds between the end of an execution and the next one. Defaults to 300.
+        #[clap(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// (Optional) Engine size to use for the query executions. Defaults to "medium".
+        #[clap(long, value_enum, ignore_case = true)]
+        engine_size: Option<EngineSize>,
+
+        /// (Optional) Query parameters in JSON format.
+        #[clap(long, value_parser = parse_params_json)]
         params: Option<JsonValue>,
 
-        /// (Optional) Whether to retrieve all rows, or only the first 10 records.
-        #[clap(short, long)]
-        peak: Option<bool>,
+        /// (Optional) Path to a JSON file with the query parameters, as an object.
+        #[clap(long, conflicts_with = "params")]
+        params_file: Option<PathBuf>,
+
+        /// (Optional) Only show the first N records (10 if no count is given) of each execution.
+        #[clap(short, long, value_name = "N", num_args = 0..=1, default_missing_value = "10", value_parser = parse_peak)]
+        peak: Option<u64>,
+
+        #[command(flatten)]
+        poll: PollArgs,
+    },
+
+    /// Compare the results of two executions, e.g. of the same query on different days, by
+    /// joining their rows on a key column. The removed (`-`), added (`+`) and changed (`~`)
+    /// rows are printed to stdout.
+    Diff {
+        /// The unique identifier of the first (e.g. older) execution.
+        #[clap(long)]
+        left: String,
+
+        /// The unique identifier of the second (e.g. newer) execution.
+        #[clap(long)]
+        right: String,
+
+        /// Column identifying the rows, whose values must be unique in each execution.
+        #[clap(long)]
+        key: String,
+    },
 
-        /// (Optional) Path where the resulting CSV file should be saved.
+    /// List the executions previously submitted from this machine, most recent first.
+    History {
+        /// (Optional) Only list the executions of this query.
         #[clap(long)]
-        path_csv: Option<String>,
+        query_id: Option<u64>,
+
+        /// (Optional) Maximum number of executions to list.
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
     },
+
+    /// Print the version, along with the git commit and the date of the build.
+    Version,
+
+    /// Manage the local configuration of the CLI.
+    #[cfg(feature = "keyring")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+/// Options shared by the commands that wait for an execution to complete.
+#[derive(Args, Debug)]
+struct PollArgs {
+    /// (Optional) Seconds between execution status checks. Defaults to 5.
+    #[clap(long)]
+    poll_interval: Option<u64>,
+
+    /// (Optional) Maximum seconds to wait for the execution to complete before giving up.
+    #[clap(long)]
+    max_wait: Option<u64>,
+}
+
+/// Options shared by the commands that retrieve results.
+#[derive(Args, Debug)]
+struct ResultsArgs {
+    /// (Optional) Only retrieve the first N records (10 if no count is given) instead of all
+    /// rows. "true" and "false" are accepted as well, "false" retrieving all rows.
+    #[clap(short, long, value_name = "N", num_args = 0..=1, default_missing_value = "10", value_parser = parse_peak)]
+    peak: Option<u64>,
+
+    /// (Optional) Fetch a single page of `--limit` rows instead of all of them.
+    #[clap(long)]
+    single_page: bool,
+
+    /// (Optional) Maximum number of rows to retrieve, across all the pages (see `--limit`).
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_rows: Option<u64>,
+
+    /// (Optional) Offset of the first row to retrieve.
+    #[clap(long, default_value_t = 0)]
+    offset: u64,
+
+    /// (Optional) Rows per page. Defaults to 1000, or to the count of `--peak` with `--peak`.
+    #[clap(long)]
+    limit: Option<u64>,
+
+    /// (Optional) Comma-separated columns to retrieve, e.g. "block_time,value". Defaults to all.
+    #[clap(long, value_delimiter = ',')]
+    columns: Option<Vec<String>>,
+
+    /// (Optional) Sort order of the rows, e.g. "block_time desc".
+    #[clap(long)]
+    sort_by: Option<String>,
+
+    /// (Optional) Lift the maximum datapoints per request, so that larger pages (see `--limit`)
+    /// can be fetched in fewer requests. This can increase the credits used per request.
+    #[clap(long)]
+    ignore_datapoint_limit: bool,
+
+    /// (Optional) Path where the resulting CSV file should be saved.
+    /// Use "-" to write the CSV to stdout.
+    #[clap(short = 'o', long, visible_alias = "output")]
+    path_csv: Option<String>,
+
+    /// (Optional) Path where the rows should be saved as a JSON array, keeping nested values.
+    #[clap(long, conflicts_with_all = ["page_files", "checkpoint"])]
+    path_json: Option<String>,
+
+    /// (Optional) Path where the results should be saved as a Parquet file.
+    #[clap(long, conflicts_with_all = ["page_files", "checkpoint"])]
+    path_parquet: Option<String>,
+
+    /// (Optional) Path where the results should be saved as an Excel workbook (`.xlsx`).
+    #[clap(long, conflicts_with_all = ["page_files", "checkpoint"])]
+    path_xlsx: Option<String>,
+
+    /// (Optional) Path of a SQLite database where the results should be saved into a table.
+    #[clap(long, conflicts_with_all = ["page_files", "checkpoint"])]
+    path_sqlite: Option<String>,
+
+    /// (Optional) Name of the SQLite table. Defaults to `dune_<QUERY_ID>`.
+    #[clap(long = "table", requires = "path_sqlite")]
+    sqlite_table: Option<String>,
+
+    /// (Optional) Drop and recreate the SQLite table if it already exists.
+    #[clap(
+        long = "replace",
+        requires = "path_sqlite",
+        conflicts_with = "sqlite_append"
+    )]
+    sqlite_replace: bool,
+
+    /// (Optional) Insert the rows into the SQLite table if it already exists.
+    #[clap(long = "append", requires = "path_sqlite")]
+    sqlite_append: bool,
+
+    /// (Optional) Format of the results written to stdout when no output path is given.
+    /// Defaults to "json".
+    #[clap(long, value_enum, conflicts_with_all = ["path_csv", "page_files", "path_parquet", "path_json", "path_xlsx", "path_sqlite"])]
+    format: Option<utils::OutputFormat>,
+
+    /// (Optional) Delimiter of the CSV output, a single ASCII character. Use "\t" for tabs.
+    /// Defaults to ";".
+    #[clap(long, value_parser = parse_csv_delimiter)]
+    csv_delimiter: Option<u8>,
+
+    /// (Optional) Directory where relative output paths are written.
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+
+    /// (Optional) Encoding of the varbinary columns (e.g. addresses) in the CSV output.
+    /// Defaults to the 0x-prefixed hex returned by Dune.
+    #[clap(long, value_enum)]
+    decode_varbinary: Option<utils::VarbinaryEncoding>,
+
+    /// (Optional) Decimals of the `double` columns in the CSV output, e.g. 2 to write 1234.57.
+    /// Defaults to the values returned by Dune.
+    #[clap(long)]
+    float_precision: Option<usize>,
+
+    /// (Optional) Write the CSV for Excel: with a UTF-8 BOM, so that non-ASCII characters
+    /// aren't garbled, and CRLF line endings.
+    #[clap(long)]
+    csv_excel: bool,
+
+    /// (Optional) Gzip-compress the CSV output.
+    #[clap(long)]
+    gzip: bool,
+
+    /// (Optional) Maximum time, in seconds, to wait for each page of results.
+    /// A page that times out is retried (3 times) before the download fails.
+    #[clap(long)]
+    timeout_per_page: Option<u64>,
+
+    /// (Optional) Maximum time, in seconds, for the whole operation.
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// (Optional) Exit with an error (code 4) if no rows are returned, e.g. to detect a broken
+    /// query or wrong parameters in a scheduled job.
+    #[clap(long)]
+    fail_on_empty: bool,
+
+    /// (Optional) Path where a JSON summary of the run should be saved (e.g. as a CI artifact).
+    #[clap(long)]
+    summary_json: Option<String>,
+
+    /// (Optional) Comma-separated order of the exported columns, e.g. "col3,col1,col2".
+    /// Columns that aren't listed are appended after them, unless `--drop-unlisted-columns` is set.
+    #[clap(long, value_delimiter = ',')]
+    column_order: Option<Vec<String>>,
+
+    /// (Optional) Drop the columns that aren't listed in `--column-order`.
+    #[clap(long, requires = "column_order")]
+    drop_unlisted_columns: bool,
+
+    /// (Optional) Directory where each page of results is written to its own numbered CSV file
+    /// (`page_00001.csv`, ...) as soon as it arrives.
+    #[clap(long, conflicts_with = "path_csv")]
+    page_files: Option<String>,
+
+    /// (Optional) Upload the results as a new Dune table with the given name.
+    #[clap(long, conflicts_with = "page_files")]
+    upload_as: Option<String>,
+
+    /// (Optional) Make the table uploaded with `--upload-as` private.
+    #[clap(long, requires = "upload_as")]
+    upload_private: bool,
+
+    /// (Optional) Append each page to the CSV file as it arrives, checkpointing the progress
+    /// to a sidecar `<path-csv>.checkpoint.json` file after every page.
+    #[clap(long, requires = "path_csv", conflicts_with_all = ["page_files", "gzip", "upload_as"])]
+    checkpoint: bool,
+
+    /// (Optional) Resume a previous run: reattach to the execution previously submitted for the
+    /// query (execute-get-results), and continue a `--checkpoint` download from its last checkpoint.
+    #[clap(long)]
+    resume: bool,
+
+    /// (Optional) Download the CSV as rendered by Dune (comma-delimited), instead of converting
+    /// the JSON results. Falls back to the conversion when other options require it.
+    #[clap(long, requires = "path_csv", conflicts_with_all = ["page_files", "checkpoint"])]
+    native_csv: bool,
+
+    /// (Optional) Fetch the pages concurrently, at most N at a time, once the first page tells
+    /// how many rows there are. The rows are kept in order. As the pages aren't fetched in order,
+    /// an interrupted export (e.g. with Ctrl-C) saves none of the rows.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["peak", "single_page", "page_files", "checkpoint", "native_csv"])]
+    parallel: Option<u64>,
+
+    /// Whether to append to the CSV file without repeating the headers, set by `--resume-from`.
+    #[clap(skip)]
+    append: bool,
+}
+
+impl ResultsArgs {
+    fn results_options(&self) -> ResultsOptions {
+        ResultsOptions {
+            offset: self.offset,
+            limit: self.limit,
+            columns: self.columns.clone(),
+            sort_by: self.sort_by.clone(),
+            params: None,
+            ignore_datapoint_limit: self.ignore_datapoint_limit,
+            single_page: self.single_page,
+            max_rows: self.max_rows,
+        }
+    }
+
+    // Number of records to peak at, if any.
+    fn peak(&self) -> Option<u64> {
+        self.peak.filter(|rows| *rows > 0)
+    }
+
+    fn csv_options(&self) -> utils::CsvOptions {
+        utils::CsvOptions {
+            delimiter: self.csv_delimiter.unwrap_or(DEFAULT_CSV_DELIMITER),
+            column_order: self.column_order.clone().map(|columns| utils::ColumnOrder {
+                columns,
+                drop_unlisted: self.drop_unlisted_columns,
+            }),
+            gzip: self.gzip,
+            headers: !self.append,
+            append: self.append,
+            varbinary: self.decode_varbinary,
+            float_precision: self.float_precision,
+            excel: self.csv_excel,
+        }
+    }
+
+    // Fills the options that weren't given on the command line from the config file, and
+    // resolves the relative output paths against the output directory.
+    fn with_config(mut self, config: &config::Config) -> Self {
+        if self.csv_delimiter.is_none() {
+            self.csv_delimiter = config.csv_delimiter.as_deref().map(|delimiter| {
+                parse_csv_delimiter(delimiter).unwrap_or_else(|e| {
+                    error!("Invalid csv_delimiter in the config file: {}", e);
+                    std::process::exit(EXIT_ERROR);
+                })
+            });
+        }
+        if self.output_dir.is_none() {
+            self.output_dir = config.output_dir.clone();
+        }
+
+        if let Some(dir) = self.output_dir.clone() {
+            let in_dir =
+                |path: String| match utils::is_stdout(&path) || Path::new(&path).is_absolute() {
+                    true => path,
+                    false => dir.join(path).to_string_lossy().into_owned(),
+                };
+            self.path_csv = self.csv_path().map(in_dir);
+            self.path_json = self.json_path().map(in_dir);
+            self.path_parquet = self.path_parquet.map(in_dir);
+            self.path_xlsx = self.path_xlsx.map(in_dir);
+            self.path_sqlite = self.path_sqlite.map(in_dir);
+            self.page_files = self.page_files.map(in_dir);
+            self.summary_json = self.summary_json.map(in_dir);
+
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                error!("Error creating the output directory: {:?}", e);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+        self
+    }
+
+    // Path of the CSV output file, if any.
+    fn csv_path(&self) -> Option<String> {
+        self.path_csv.as_deref().map(|path_csv| match path_csv {
+            "true" => format!("output.{}", self.csv_extension()),
+            path => path.to_string(),
+        })
+    }
+
+    // The first option that the CSV rendered by Dune can't honor, so that the JSON results
+    // have to be converted instead, if any.
+    fn native_csv_blocker(
+        &self,
+        filters: &QueryResultsFilter,
+        options: &ResultsOptions,
+    ) -> Option<&'static str> {
+        [
+            (filters.to_option_string().is_some(), "--filter"),
+            (options.params.is_some(), "--params"),
+            (self.peak().is_some(), "--peak"),
+            (self.single_page, "--single-page"),
+            (options.offset > 0, "--offset"),
+            (self.limit.is_some(), "--limit"),
+            (self.max_rows.is_some(), "--max-rows"),
+            (self.columns.is_some(), "--columns"),
+            (self.sort_by.is_some(), "--sort-by"),
+            (self.column_order.is_some(), "--column-order"),
+            (self.decode_varbinary.is_some(), "--decode-varbinary"),
+            (self.float_precision.is_some(), "--float-precision"),
+            (self.csv_excel, "--csv-excel"),
+            (self.fail_on_empty, "--fail-on-empty"),
+            (
+                self.csv_delimiter
+                    .is_some_and(|delimiter| delimiter != b','),
+                "--csv-delimiter",
+            ),
+            (self.append, "--resume-from"),
+            (self.path_json.is_some(), "--path-json"),
+            (self.path_parquet.is_some(), "--path-parquet"),
+            (self.path_xlsx.is_some(), "--path-xlsx"),
+            (self.path_sqlite.is_some(), "--path-sqlite"),
+            (self.upload_as.is_some(), "--upload-as"),
+            (self.summary_json.is_some(), "--summary-json"),
+        ]
+        .into_iter()
+        .find(|(blocks, _)| *blocks)
+        .map(|(_, flag)| flag)
+    }
+
+    // How an existing SQLite table is handled.
+    fn sqlite_mode(&self) -> utils::SqliteMode {
+        match (self.sqlite_replace, self.sqlite_append) {
+            (true, _) => utils::SqliteMode::Replace,
+            (false, true) => utils::SqliteMode::Append,
+            (false, false) => utils::SqliteMode::Create,
+        }
+    }
+
+    // Path of the JSON output file, if any.
+    fn json_path(&self) -> Option<String> {
+        self.path_json.as_deref().map(|path_json| match path_json {
+            "true" => "output.json".to_string(),
+            path => path.to_string(),
+        })
+    }
+
+    // Whether the CSV is written as the pages arrive. Uploads, Parquet and JSON files need all
+    // the rows in memory anyway.
+    fn streams_csv(&self) -> bool {
+        self.parallel.is_none()
+            && self.upload_as.is_none()
+            && self.path_parquet.is_none()
+            && self.path_json.is_none()
+            && self.path_xlsx.is_none()
+            && self.path_sqlite.is_none()
+    }
+
+    // File extension of the CSV output files.
+    fn csv_extension(&self) -> &str {
+        match self.gzip {
+            true => "csv.gz",
+            false => "csv",
+        }
+    }
+}
+
+fn parse_csv_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "\\t" => Ok(b'\t'),
+        s if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err("the delimiter must be a single ASCII character".to_string()),
+    }
+}
+
+/// Available commands for managing the local configuration.
+#[cfg(feature = "keyring")]
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Prompt for the API key and store it in the OS keyring.
+    SetKey,
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let cli = Cli::parse();
+
+    // logs go to stderr, so that stdout only carries the results
     let tracing_sub = tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_env_filter(log_filter(cli.quiet, cli.verbose, cli.trace))
         .with_target(false)
-        .finish();
-    tracing::subscriber::set_global_default(tracing_sub)
-        .expect("Setting tracing subscriber failed");
+        .with_writer(std::io::stderr);
+    match cli.log_format {
+        LogFormat::Text => tracing::subscriber::set_global_default(tracing_sub.finish()),
+        LogFormat::Json => tracing::subscriber::set_global_default(tracing_sub.json().finish()),
+    }
+    .expect("Setting tracing subscriber failed");
 
-    let cli = Cli::parse();
+    if let Commands::Version = &cli.command {
+        println!("dune-cli {}", LONG_VERSION);
+        return;
+    }
+
+    // config commands don't require an API key
+    #[cfg(feature = "keyring")]
+    if let Commands::Config { command } = &cli.command {
+        match command {
+            ConfigCommands::SetKey => match credentials::prompt_and_store_api_key() {
+                Ok(_) => info!("API key stored in the OS keyring"),
+                Err(e) => error!("Error storing the API key in the OS keyring: {:?}", e),
+            },
+        }
+        return;
+    }
+
+    // the history is local, so it doesn't need an API key
+    if let Commands::History { query_id, limit } = &cli.command {
+        print_history(*query_id, *limit);
+        return;
+    }
+
+    let config = match config::load_config(cli.config.as_deref(), cli.profile.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error reading the config file: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    let resolve_engine_size = |engine_size: Option<EngineSize>| {
+        engine_size
+            .or(config.engine_size)
+            .unwrap_or(EngineSize::Medium)
+    };
 
     // ensure API key is set
-    let api_key = cli
-        .api_key
-        .unwrap_or_else(|| std::env::var("DUNE_API_KEY").expect("DUNE_API_KEY must be set"));
+    let api_key = match credentials::resolve_api_key(
+        cli.api_key,
+        cli.api_key_file.as_deref(),
+        config.api_key.clone(),
+    ) {
+        Ok(Some(api_key)) => api_key,
+        // the key is redacted from the logged requests anyway
+        Ok(None) if cli.dry_run => String::new(),
+        Ok(None) => {
+            eprintln!("error: no Dune API key provided (set --api-key, DUNE_API_KEY or api_key in the config file)");
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(e) => {
+            error!("Error reading the API key: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
 
     match cli.command {
         Commands::Execute {
             id,
+            query_name,
             engine_size,
             params,
+            params_file,
+            json,
         } => {
-            let performance = match engine_size {
-                Some(size) => match size.to_lowercase().as_str() {
-                    "medium" => EngineSize::Medium,
-                    "m" => EngineSize::Medium,
-                    "large" => EngineSize::Large,
-                    "l" => EngineSize::Large,
-                    _ => {
-                        error!("Invalid performance level. Use 'medium' or 'large'.");
-                        return;
+            let params = resolve_params(params, params_file);
+            let engine_size = resolve_engine_size(engine_size);
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let id = match (id, query_name) {
+                (Some(id), _) => id,
+                (None, Some(name)) => match client.find_query_id_by_name(&name).await {
+                    Ok(id) => {
+                        info!("Query '{}' has id {}", name, id);
+                        id
                     }
+                    Err(e) => exit_with_error(e),
                 },
-                None => EngineSize::Medium,
+                (None, None) => unreachable!("clap requires --id or --query-name"),
             };
-            let client = DuneClient::new(api_key);
-            match client.execute_query(id, performance, params).await {
-                Ok(res) => info!("Response: {:?}", res),
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return;
+            match client.execute_query(id, engine_size, params).await {
+                Ok(res) => {
+                    info!("Response: {:?}", res);
+                    record_history(id, &res.execution_id, engine_size);
+                    if json {
+                        print_json(&res);
+                    }
                 }
+                Err(e) => exit_with_error(e),
             };
         }
-        Commands::GetStatus { id } => {
-            let client = DuneClient::new(api_key);
-            match client.get_execution_status(&id).await {
-                Ok(res) => info!("Response: {:?}", res),
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return;
-                }
+        Commands::Sql {
+            query,
+            sql_file,
+            engine_size,
+        } => {
+            let sql = match (query, sql_file) {
+                (Some(query), _) => query,
+                (None, Some(path)) => read_sql_file(&path),
+                (None, None) => unreachable!("clap requires --query or --sql-file"),
             };
-        }
-        Commands::GetMaterializedView { id } => {
-            let client = DuneClient::new(api_key);
-            match client.get_materialized_view_results(&id).await {
+            if sql.trim().is_empty() {
+                error!("The SQL to execute is empty");
+                std::process::exit(EXIT_ERROR);
+            }
+            let engine_size = resolve_engine_size(engine_size);
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.execute_sql(&sql, engine_size).await {
                 Ok(res) => info!("Response: {:?}", res),
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return;
-                }
+                Err(e) => exit_with_error(e),
             };
         }
-        Commands::GetResults {
-            id,
-            filter,
-            peak,
-            path_csv,
+        Commands::CreateQuery {
+            name,
+            sql_file,
+            parameters,
+            description,
+            private,
         } => {
-            let client = DuneClient::new(api_key);
-            let res = match client
-                .get_query_results(
-                    &id,
-                    match filter {
-                        Some(filter) => QueryResultsFilter::new().add_filter(filter),
-                        None => QueryResultsFilter::new(),
-                    },
-                    peak.unwrap_or(false),
+            let sql = read_sql_file(&sql_file);
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client
+                .create_query(
+                    &name,
+                    sql,
+                    parameters.unwrap_or_default(),
+                    description,
+                    private,
                 )
                 .await
             {
-                Ok(res) => res,
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return;
-                }
+                Ok(res) => info!("Query '{}' created with id {}", name, res.query_id),
+                Err(e) => exit_with_error(e),
             };
-
-            // save results to CSV if path is provided
-            match path_csv {
-                Some(path_csv) => {
-                    match utils::save_json_as_csv(
-                        res.rows,
-                        match path_csv.as_str() {
-                            "true" => "output.csv",
-                            path => path,
-                        },
-                    )
-                    .await
-                    {
-                        Ok(_) => info!("Results saved to CSV"),
-                        Err(e) => error!("Error saving results to CSV file: {:?}", e),
-                    };
-                }
-                None => info!("Results: {:?}", res),
-            }
         }
-        Commands::ExecuteGetResults {
+        Commands::UpdateQuery {
             id,
-            engine_size,
-            params,
-            peak,
-            path_csv,
+            sql_file,
+            name,
+            parameters,
+            description,
         } => {
-            let performance = match engine_size {
-                Some(size) => match size.to_lowercase().as_str() {
-                    "medium" => EngineSize::Medium,
-                    "m" => EngineSize::Medium,
-                    "large" => EngineSize::Large,
-                    "l" => EngineSize::Large,
-                    _ => {
-                        error!("Invalid performance level. Use 'medium' or 'large'.");
-                        return;
-                    }
-                },
-                None => EngineSize::Medium,
+            let changes = UpdateQueryParams {
+                name,
+                query_sql: sql_file.as_deref().map(read_sql_file),
+                parameters,
+                description,
             };
-            let client = DuneClient::new(api_key);
-            let res = match client
-                .execute_query_and_get_results_when_ready(
-                    id,
-                    performance,
-                    params,
-                    None,
-                    peak.unwrap_or(false),
-                )
-                .await
+            if changes.name.is_none()
+                && changes.query_sql.is_none()
+                && changes.parameters.is_none()
+                && changes.description.is_none()
             {
-                Ok(res) => res,
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return;
-                }
+                error!(
+                    "Nothing to update: give at least one of --sql-file, --name, --parameters \
+                    or --description"
+                );
+                std::process::exit(EXIT_USAGE);
+            }
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.update_query(id, changes).await {
+                Ok(res) => info!("Query {} updated", res.query_id),
+                Err(e) => exit_with_error(e),
             };
-
-            // save results to CSV if path is provided
-            match path_csv {
-                Some(path_csv) => {
-                    match utils::save_json_as_csv(
-                        res.rows,
-                        match path_csv.as_str() {
-                            "true" => "output.csv",
-                            path => path,
-                        },
+        }
+        Commands::ExecuteBatch { file, concurrency } => {
+            let queries: Vec<(u64, EngineSize, Option<JsonValue>)> = read_batch_file(&file)
+                .into_iter()
+                .map(|query| {
+                    (
+                        query.query_id,
+                        resolve_engine_size(query.engine_size),
+                        query.params,
                     )
-                    .await
-                    {
-                        Ok(_) => info!("Results saved to CSV"),
-                        Err(e) => error!("Error saving results to CSV file: {:?}", e),
-                    };
+                })
+                .collect();
+
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let responses = client.execute_many(queries.clone(), concurrency).await;
+            let mut failed = 0;
+            for ((query_id, engine_size, _), res) in queries.into_iter().zip(responses) {
+                match res {
+                    Ok(res) => {
+                        info!("Query {}: {:?}", query_id, res);
+                        record_history(query_id, &res.execution_id, engine_size);
+                    }
+                    Err(e) => {
+                        error!("Query {}: {:?}", query_id, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                error!("{} of the executions failed", failed);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+        Commands::GetStatus { id, json } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.get_execution_status(&id).await {
+                Ok(res) => {
+                    info!("Response: {:?}", res);
+                    if json {
+                        print_json(&res);
+                    }
+                    log_timestamps(&res);
+                    if let Some(position) = res.queue_position {
+                        info!("Queue position: {}", position);
+                    }
+                    if let Some(metadata) = &res.result_metadata {
+                        log_timing_summary(metadata);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        Commands::Cancel { id } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.cancel_execution(&id).await {
+                Ok(res) => info!("Response: {:?}", res),
+                Err(e) => exit_with_error(e),
+            };
+        }
+        Commands::Wait { id, poll, results } => {
+            let poll_interval = poll.poll_interval.or(config.poll_interval);
+            let results = results.with_config(&config);
+            let started_at = Instant::now();
+            let client = new_client(
+                api_key,
+                config.base_url.as_deref(),
+                results.timeout_per_page,
+                cli.dry_run,
+            );
+            let (res, row_count) = match with_timeout(results.timeout, async {
+                client
+                    .wait_until_finished(&id, poll_interval, poll.max_wait)
+                    .await?;
+                fetch_results(
+                    &client,
+                    &id,
+                    QueryResultsFilter::new(),
+                    results.results_options(),
+                    &results,
+                )
+                .await
+            })
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            output_results(&client, res, row_count, results, started_at).await;
+        }
+        Commands::GetQuery { id } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.get_query(id).await {
+                Ok(res) => print_query_metadata(&res),
+                Err(e) => exit_with_error(e),
+            };
+        }
+        Commands::GetMaterializedView { id, results } => {
+            let results = results.with_config(&config);
+            let started_at = Instant::now();
+            let client = new_client(
+                api_key,
+                config.base_url.as_deref(),
+                results.timeout_per_page,
+                cli.dry_run,
+            );
+            let view = match client.get_materialized_view_results(&id).await {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+            info!("Materialized view: {:?}", view);
+
+            // the rows of the view are the results of the latest execution of its query
+            let (res, row_count) = match with_timeout(
+                results.timeout,
+                fetch_results(
+                    &client,
+                    &view.query_id.to_string(),
+                    QueryResultsFilter::new(),
+                    results.results_options(),
+                    &results,
+                ),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            output_results(&client, res, row_count, results, started_at).await;
+        }
+        Commands::Credits => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            match client.get_account_info().await {
+                Ok(res) => print_account_info(&res),
+                Err(e) => exit_with_error(e),
+            };
+        }
+        Commands::RefreshView {
+            id,
+            engine_size,
+            wait,
+            poll,
+        } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let res = match client
+                .refresh_materialized_view(&id, resolve_engine_size(engine_size))
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+            info!("Response: {:?}", res);
+
+            if wait {
+                let poll_interval = poll.poll_interval.or(config.poll_interval);
+                match client
+                    .wait_until_finished(&res.execution_id, poll_interval, poll.max_wait)
+                    .await
+                {
+                    Ok(_) => info!("Materialized view {} refreshed", id),
+                    Err(e) => exit_with_error(e),
+                }
+            }
+        }
+        Commands::GetResults {
+            id,
+            filter,
+            since,
+            until,
+            time_column,
+            params,
+            resume_from,
+            results,
+        } => {
+            // an execution already ran with its own parameters
+            if params.is_some() && matches!(id, ResultId::Execution(_)) {
+                exit_with_error(DuneError::InvalidParams(
+                    "--params only applies to a query ID, not to an execution ID".to_string(),
+                ));
+            }
+            let mut results = results.with_config(&config);
+            if let Some(offset) = resume_from {
+                results.offset = offset;
+                results.append = true;
+            }
+            let mut filters = filter
+                .iter()
+                .map(|filter| filter.trim())
+                .filter(|filter| !filter.is_empty())
+                .fold(QueryResultsFilter::new(), |filters, filter| {
+                    filters.add_filter(filter.to_string())
+                });
+            if let Some(since) = since {
+                filters = filters.add_typed_filter(Filter::new(&time_column, FilterOp::Gte, since));
+            }
+            if let Some(until) = until {
+                filters = filters.add_typed_filter(Filter::new(&time_column, FilterOp::Lt, until));
+            }
+            let started_at = Instant::now();
+            let client = new_client(
+                api_key,
+                config.base_url.as_deref(),
+                results.timeout_per_page,
+                cli.dry_run,
+            );
+            let (res, row_count) = match with_timeout(
+                results.timeout,
+                fetch_results(
+                    &client,
+                    &id.to_string(),
+                    filters,
+                    ResultsOptions {
+                        params,
+                        ..results.results_options()
+                    },
+                    &results,
+                ),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            output_results(&client, res, row_count, results, started_at).await;
+        }
+        Commands::Schema { id, as_ddl, table } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let id = id.to_string();
+            let metadata = match client.get_results_metadata(&id).await {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            match as_ddl {
+                Some(dialect) => println!(
+                    "{}",
+                    schema::create_table_ddl(
+                        &table.unwrap_or_else(|| format!("dune_{}", id.to_lowercase())),
+                        &metadata.column_names,
+                        &metadata.column_types,
+                        dialect,
+                    )
+                ),
+                None => print!(
+                    "{}",
+                    utils::format_schema_table(&metadata.column_names, &metadata.column_types)
+                ),
+            }
+        }
+        Commands::LatestResult {
+            id,
+            params,
+            results,
+        } => {
+            let results = results.with_config(&config);
+            let started_at = Instant::now();
+            let client = new_client(
+                api_key,
+                config.base_url.as_deref(),
+                results.timeout_per_page,
+                cli.dry_run,
+            );
+            let options = ResultsOptions {
+                params,
+                ..results.results_options()
+            };
+            let (res, row_count) = match with_timeout(
+                results.timeout,
+                fetch_results(
+                    &client,
+                    &id.to_string(),
+                    QueryResultsFilter::new(),
+                    options,
+                    &results,
+                ),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            output_results(&client, res, row_count, results, started_at).await;
+        }
+        Commands::ExecuteGetResults {
+            id,
+            engine_size,
+            params,
+            params_file,
+            poll,
+            results,
+        } => {
+            let params = resolve_params(params, params_file);
+            let engine_size = resolve_engine_size(engine_size);
+            let poll_interval = poll.poll_interval.or(config.poll_interval);
+            let results = results.with_config(&config);
+            let started_at = Instant::now();
+            let client = new_client(
+                api_key,
+                config.base_url.as_deref(),
+                results.timeout_per_page,
+                cli.dry_run,
+            );
+            let (res, row_count) = match with_timeout(results.timeout, async {
+                let execution_id =
+                    submit_or_resume_execution(&client, id, engine_size, params, results.resume)
+                        .await?;
+                client
+                    .wait_until_finished(&execution_id, poll_interval, poll.max_wait)
+                    .await?;
+                fetch_results(
+                    &client,
+                    &execution_id,
+                    QueryResultsFilter::new(),
+                    results.results_options(),
+                    &results,
+                )
+                .await
+            })
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => exit_with_error(e),
+            };
+
+            // the execution has completed, so there is nothing left to resume
+            if let Err(e) = state::remove_execution_state(id) {
+                error!("Error removing the execution state: {:?}", e);
+            }
+
+            output_results(&client, res, row_count, results, started_at).await;
+        }
+        Commands::Watch {
+            id,
+            interval,
+            engine_size,
+            params,
+            params_file,
+            peak,
+            poll,
+        } => {
+            let params = resolve_params(params, params_file);
+            let engine_size = resolve_engine_size(engine_size);
+            let poll_interval = poll.poll_interval.or(config.poll_interval);
+            let peak = peak.filter(|&n| n > 0);
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+            for cycle in 0.. {
+                let refresh = client.execute_query_and_get_results_when_ready(
+                    id,
+                    engine_size,
+                    params.clone(),
+                    poll_interval,
+                    poll.max_wait,
+                    peak,
+                );
+                tokio::select! {
+                    res = refresh => match res {
+                        Ok(res) => draw_watched_results(&res, interval),
+                        // most likely a problem with the query or the key, which won't go away
+                        Err(e) if cycle == 0 => exit_with_error(e),
+                        Err(e) => error!(
+                            "Error refreshing the results, retrying in {} seconds: {:?}",
+                            interval, e
+                        ),
+                    },
+                    _ = &mut ctrl_c => break,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+                    _ = &mut ctrl_c => break,
+                }
+            }
+            info!("Stopped watching query {}", id);
+        }
+        Commands::Diff { left, right, key } => {
+            let client = new_client(api_key, config.base_url.as_deref(), None, cli.dry_run);
+            let fetch = |id: String| {
+                let client = &client;
+                async move {
+                    client
+                        .get_query_results(&id, QueryResultsFilter::new(), Default::default(), None)
+                        .await
+                }
+            };
+            let (left, right) = match tokio::try_join!(fetch(left), fetch(right)) {
+                Ok(results) => results,
+                Err(e) => exit_with_error(e),
+            };
+            if left.query_id != right.query_id {
+                warn!(
+                    "The executions are of different queries ({} and {})",
+                    left.query_id, right.query_id
+                );
+            }
+
+            // columns of either execution, in the order of the first one
+            let mut columns = left.metadata.column_names.clone();
+            for column in &right.metadata.column_names {
+                if !columns.contains(column) {
+                    columns.push(column.clone());
+                }
+            }
+            for res in [&left, &right] {
+                if !res.metadata.column_names.contains(&key) {
+                    error!(
+                        "No column {} in the results of execution {}. Available columns: {}",
+                        key,
+                        res.execution_id,
+                        res.metadata.column_names.join(", ")
+                    );
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+
+            let diff = match diff::diff_rows(&left.rows, &right.rows, &key, &columns) {
+                Ok(diff) => diff,
+                Err(e) => {
+                    error!("Error comparing the results: {}", e);
+                    std::process::exit(EXIT_ERROR);
+                }
+            };
+            print!("{}", diff.format(&key));
+            info!(
+                "{} rows removed, {} added, {} changed, {} unchanged",
+                diff.removed.len(),
+                diff.added.len(),
+                diff.changed.len(),
+                diff.unchanged
+            );
+        }
+        Commands::History { .. } => unreachable!("the history command is handled above"),
+        Commands::Version => unreachable!("the version command is handled above"),
+        #[cfg(feature = "keyring")]
+        Commands::Config { .. } => unreachable!("config commands are handled above"),
+    }
+}
+
+// Log filter for the verbosity flags. Without them, `RUST_LOG` applies, defaulting to INFO.
+// Request and response dumps are logged at TRACE, so they are only shown with `--trace`.
+fn log_filter(quiet: bool, verbose: u8, trace: bool) -> EnvFilter {
+    let level = match (quiet, verbose, trace) {
+        (_, _, true) => LevelFilter::TRACE,
+        (true, _, _) => LevelFilter::ERROR,
+        (false, 0, false) => {
+            return EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy()
+        }
+        (false, _, false) => LevelFilter::DEBUG,
+    };
+    EnvFilter::default().add_directive(level.into())
+}
+
+// Logs the error, with a hint on how to fix it when there is one, and exits with its code.
+fn exit_with_error(e: DuneError) -> ! {
+    match &e {
+        DuneError::PaymentRequired(message) => {
+            error!("Payment required: {}", message);
+            error!(
+                "Hint: check that your Dune plan includes API access, has credits left, \
+                and is entitled to the requested engine size."
+            );
+        }
+        DuneError::AmbiguousQueryName { name, candidates } => {
+            error!("Several queries are named '{}':", name);
+            for query in candidates {
+                error!("  {} ({})", query.id, query.name);
+            }
+            error!("Hint: use `--id` with the id of the query to execute.");
+        }
+        DuneError::EmptyResults { execution_id } => {
+            error!("Execution {} returned no rows", execution_id);
+            error!(
+                "Hint: check the query and its parameters, or drop `--fail-on-empty` if empty \
+                results are expected."
+            );
+        }
+        DuneError::ParseError { source, body } => {
+            error!("Unexpected response from the API: {}", source);
+            if let Some(body) = body {
+                error!("Response body: {}", body);
+            }
+        }
+        DuneError::ApiError { status, message } => {
+            let reason = StatusCode::from_u16(*status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or_default();
+            error!("API error {} {}: {}", status, reason, message);
+            if *status == StatusCode::UNAUTHORIZED.as_u16() {
+                error!("Hint: check that the API key is valid.");
+            }
+        }
+        DuneError::ResultsExpired { execution_id } => {
+            error!("The results of execution {} have expired.", execution_id);
+            error!(
+                "Hint: execute the query again with `execute-get-results --id <QUERY_ID>` to \
+                get fresh results."
+            );
+        }
+        DuneError::InvalidParams(message) => {
+            error!("Invalid query parameters: {}", message);
+        }
+        DuneError::QueryStatusError { status, message } => {
+            match message {
+                Some(message) => error!("Execution ended in state {:?}: {}", status, message),
+                None => error!("Execution ended in state {:?}", status),
+            }
+            if *status == ExecutionStatus::QueryStateFailed {
+                error!(
+                    "Hint: fix the query if it's invalid, or run it with a larger \
+                    `--engine-size` if it ran out of memory or time."
+                );
+            }
+        }
+        DuneError::DryRun => {
+            info!("Dry run: the request was not sent.");
+        }
+        DuneError::PollTimeout(execution_id) => {
+            error!(
+                "Execution {} didn't complete within the maximum wait time.",
+                execution_id
+            );
+            error!(
+                "Hint: the execution keeps running on Dune. Fetch its results later with \
+                `get-results --id {}`.",
+                execution_id
+            );
+        }
+        DuneError::RetriesExhausted {
+            attempts,
+            last_status,
+            last_error,
+        } => match (last_status, last_error) {
+            (Some(status), _) => error!(
+                "The request failed {} times, lastly with HTTP {}",
+                attempts, status
+            ),
+            (None, Some(e)) => {
+                // the causes (e.g. a DNS or TLS error) aren't part of the error's message
+                let mut message = e.to_string();
+                let mut source = std::error::Error::source(e);
+                while let Some(cause) = source {
+                    message.push_str(&format!(": {}", cause));
+                    source = cause.source();
+                }
+                error!(
+                    "The request failed {} times, lastly with: {}",
+                    attempts, message
+                );
+            }
+            (None, None) => error!("The request failed {} times", attempts),
+        },
+        DuneError::Timeout(timeout) => {
+            error!(
+                "Error: operation timed out after {} seconds",
+                timeout.as_secs()
+            );
+        }
+        DuneError::PageTimeout { offset, attempts } => {
+            error!(
+                "The page at offset {} timed out on all {} attempts.",
+                offset, attempts
+            );
+            error!("Hint: raise `--timeout-per-page`, or check the connection to the API.");
+        }
+        e => {
+            error!("Error: {:?}", e);
+        }
+    }
+    std::process::exit(exit_code(&e));
+}
+
+// Exit code of the error, so that scripts can tell the failures apart.
+fn exit_code(e: &DuneError) -> i32 {
+    match e {
+        DuneError::PaymentRequired(_) => EXIT_PAYMENT_REQUIRED,
+        DuneError::EmptyResults { .. } => EXIT_EMPTY_RESULTS,
+        // nothing was sent, so nothing failed
+        DuneError::DryRun => 0,
+        _ => EXIT_ERROR,
+    }
+}
+
+// Logs how long the execution spent queued and executing, and the size of its result.
+// Logs the wall-clock times at which the execution was submitted, started and ended, if known.
+fn log_timestamps(status: &ExecutionStatusResponse) {
+    let timestamps = [
+        ("Submitted:", status.submitted_at),
+        ("Started:", status.execution_started_at),
+        ("Ended:", status.execution_ended_at),
+    ];
+    for (label, timestamp) in timestamps {
+        if let Some(timestamp) = timestamp {
+            info!(
+                "{:<12} {}",
+                label,
+                timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+            );
+        }
+    }
+}
+
+fn log_timing_summary(metadata: &StatusResultMetadata) {
+    if let Some(pending_time) = metadata.pending_time_millis {
+        info!("Pending:     {}", utils::format_millis(pending_time));
+    }
+    if let Some(execution_time) = metadata.execution_time_millis {
+        info!("Executing:   {}", utils::format_millis(execution_time));
+    }
+    if let Some(result_set_bytes) = metadata.total_result_set_bytes {
+        info!("Result size: {} bytes", result_set_bytes);
+    }
+}
+
+// Retrieves the results, returning them along with the number of rows retrieved.
+// With `--page-files` or `--checkpoint`, the pages are written as they arrive, so the returned
+// result holds no rows.
+async fn fetch_results(
+    client: &DuneClient,
+    id: &str,
+    filters: QueryResultsFilter,
+    options: ResultsOptions,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    if let Some(path) = args.csv_path().filter(|_| args.native_csv) {
+        match args.native_csv_blocker(&filters, &options) {
+            None => return save_native_csv(client, id, &path, args).await,
+            Some(flag) => info!(
+                "{} requires converting the JSON results, so the CSV rendered by Dune isn't used",
+                flag
+            ),
+        }
+    }
+
+    let peak = args.peak();
+    if let Some(dir) = &args.page_files {
+        return save_page_files(client.results_pager(id, filters, options, peak), dir, args).await;
+    }
+    if args.checkpoint {
+        return save_with_checkpoints(client.results_pager(id, filters, options, peak), id, args)
+            .await;
+    }
+    if let Some(path) = args.csv_path().filter(|_| args.streams_csv()) {
+        return save_streamed_csv(
+            client.results_pager(id, filters, options, peak),
+            &path,
+            args,
+        )
+        .await;
+    }
+    if let Some(path) = args.csv_path().filter(|_| args.parallel.is_none()) {
+        return fetch_buffered_csv(
+            client.results_pager(id, filters, options, peak),
+            &path,
+            args,
+        )
+        .await;
+    }
+
+    let res = match args.parallel {
+        Some(concurrency) => {
+            client
+                .get_query_results_parallel(id, filters, options, concurrency as usize)
+                .await?
+        }
+        None => client.get_query_results(id, filters, options, peak).await?,
+    };
+    let row_count = res.rows.len();
+    Ok((res, row_count))
+}
+
+// Streams the CSV rendered by Dune straight to the output. The number of rows isn't known, as
+// the CSV isn't parsed.
+async fn save_native_csv(
+    client: &DuneClient,
+    id: &str,
+    path: &str,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    let mut target = match utils::open_csv_target(path, &args.csv_options()) {
+        Ok(target) => target,
+        Err(e) => {
+            error!("Error opening the CSV file: {:?}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    let bytes = client.get_query_results_csv(id, &mut target).await?;
+    if let Err(e) = target.finish() {
+        error!("Error saving results to CSV file: {:?}", e);
+        std::process::exit(EXIT_ERROR);
+    }
+    debug!("{} bytes of CSV downloaded", bytes);
+    log_saved(path);
+    Ok((QueryResult::default(), 0))
+}
+
+// Writes each page to its own numbered CSV file in `dir` as soon as it arrives.
+async fn save_page_files(
+    mut pager: ResultsPager<'_>,
+    dir: &str,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        error!("Error creating the page files directory: {:?}", e);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    let csv_options = args.csv_options();
+    let mut res = QueryResult::default();
+    let (mut page_count, mut row_count) = (0, 0);
+    while let Some((_, response)) = pager.next_page().await? {
+        page_count += 1;
+        let path = Path::new(dir).join(format!("page_{:05}.{}", page_count, args.csv_extension()));
+        if let Err(e) = utils::save_json_as_csv(
+            &response.result.rows,
+            Some(&response.result.metadata),
+            &path.to_string_lossy(),
+            &csv_options,
+        )
+        .await
+        {
+            error!("Error saving page {} to CSV file: {:?}", page_count, e);
+            std::process::exit(EXIT_ERROR);
+        }
+        info!("Page {} saved to {}", page_count, path.display());
+        row_count += response.result.rows.len();
+
+        if page_count == 1 {
+            res = without_rows(response);
+        }
+    }
+
+    res.metadata.datapoint_count = pager.datapoint_count();
+    res.page_count = pager.page_count();
+    pager.log_summary();
+    Ok((res, row_count))
+}
+
+// Writes the rows to the CSV output as the pages arrive, instead of holding them all in memory.
+async fn save_streamed_csv(
+    mut pager: ResultsPager<'_>,
+    path: &str,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    let csv_options = args.csv_options();
+    let target = match utils::open_csv_target(path, &csv_options) {
+        Ok(target) => target,
+        Err(e) => {
+            error!("Error opening the CSV file: {:?}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
+    let mut writer = utils::CsvStreamWriter::new(target, &csv_options);
+    let mut res = QueryResult::default();
+    let (mut is_first_page, mut row_count) = (true, 0);
+    // once handled, Ctrl-C no longer kills the process, so it's only handled while downloading
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+    loop {
+        let page = tokio::select! {
+            page = pager.next_page() => Some(page),
+            _ = &mut ctrl_c => None,
+        };
+        let response = match page {
+            Some(Ok(Some((_, response)))) => response,
+            Some(Ok(None)) => break,
+            Some(Err(e)) => {
+                let resume_offset = pager.resume_offset().filter(|_| !is_first_page);
+                finish_partial_csv(writer, row_count, &res.execution_id, resume_offset);
+                return Err(e);
+            }
+            None => {
+                let resume_offset = pager.resume_offset().filter(|_| !is_first_page);
+                if finish_partial_csv(writer, row_count, &res.execution_id, resume_offset) {
+                    info!("Interrupted: {} rows saved to {}", row_count, path);
+                }
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+        };
+        writer.set_metadata(&response.result.metadata);
+        for row in &response.result.rows {
+            if let Err(e) = writer.write_row(row) {
+                error!("Error saving results to CSV file: {:?}", e);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+        row_count += response.result.rows.len();
+
+        if is_first_page {
+            res = without_rows(response);
+            is_first_page = false;
+        }
+    }
+
+    if let Err(e) = writer.finish().and_then(|target| Ok(target.finish()?)) {
+        error!("Error saving results to CSV file: {:?}", e);
+        std::process::exit(EXIT_ERROR);
+    }
+    log_saved(path);
+    res.next_offset = pager.resume_offset();
+    res.metadata.datapoint_count = pager.datapoint_count();
+    res.page_count = pager.page_count();
+    pager.log_summary();
+    Ok((res, row_count))
+}
+
+// Retrieves all the rows before the CSV is saved along with the other outputs that need them in
+// memory. On Ctrl-C, the rows fetched so far are still saved to the CSV file.
+async fn fetch_buffered_csv(
+    mut pager: ResultsPager<'_>,
+    path: &str,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    let mut res = QueryResult::default();
+    let mut is_first_page = true;
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+    loop {
+        let page = tokio::select! {
+            page = pager.next_page() => Some(page),
+            _ = &mut ctrl_c => None,
+        };
+        let mut response = match page {
+            Some(Ok(Some((_, response)))) => response,
+            Some(Ok(None)) => break,
+            Some(Err(e)) => return Err(e),
+            None => {
+                let resume_offset = pager.resume_offset().filter(|_| !is_first_page);
+                if save_partial_csv(&res, path, args, resume_offset) {
+                    info!("Interrupted: {} rows saved to {}", res.rows.len(), path);
+                }
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+        };
+        let rows = std::mem::take(&mut response.result.rows);
+        if is_first_page {
+            res = without_rows(response);
+            is_first_page = false;
+        }
+        res.rows.extend(rows);
+    }
+
+    res.next_offset = pager.resume_offset();
+    res.metadata.datapoint_count = pager.datapoint_count();
+    res.page_count = pager.page_count();
+    pager.log_summary();
+    let row_count = res.rows.len();
+    Ok((res, row_count))
+}
+
+// Saves the rows fetched before an interruption to the CSV file, as they would have been
+// streamed to it. Returns whether the CSV could be saved.
+fn save_partial_csv(
+    res: &QueryResult,
+    path: &str,
+    args: &ResultsArgs,
+    resume_offset: Option<u64>,
+) -> bool {
+    let csv_options = args.csv_options();
+    let target = match utils::open_csv_target(path, &csv_options) {
+        Ok(target) => target,
+        Err(e) => {
+            error!("Error opening the CSV file: {:?}", e);
+            return false;
+        }
+    };
+    let mut writer = utils::CsvStreamWriter::new(target, &csv_options);
+    writer.set_metadata(&res.metadata);
+    for row in &res.rows {
+        if let Err(e) = writer.write_row(row) {
+            error!("Error saving results to CSV file: {:?}", e);
+            return false;
+        }
+    }
+    finish_partial_csv(writer, res.rows.len(), &res.execution_id, resume_offset)
+}
+
+// Keeps the rows saved so far by finishing the CSV, and logs how to resume the export.
+// Returns whether the CSV could be finished.
+fn finish_partial_csv(
+    writer: utils::CsvStreamWriter<utils::CsvTarget<Box<dyn std::io::Write>>>,
+    row_count: usize,
+    execution_id: &str,
+    resume_offset: Option<u64>,
+) -> bool {
+    if let Err(e) = writer.finish().and_then(|target| Ok(target.finish()?)) {
+        error!("Error saving results to CSV file: {:?}", e);
+        return false;
+    }
+    if let Some(offset) = resume_offset {
+        error!(
+            "Export interrupted after {} rows. Resume it with \
+            `get-results --id {} --resume-from {}`.",
+            row_count, execution_id, offset
+        );
+    }
+    true
+}
+
+// Appends each page to the CSV file as soon as it arrives, checkpointing the progress
+// after every page so that an interrupted download can be resumed with `--resume`.
+async fn save_with_checkpoints(
+    mut pager: ResultsPager<'_>,
+    id: &str,
+    args: &ResultsArgs,
+) -> Result<(QueryResult, usize), DuneError> {
+    let path = args.csv_path().unwrap_or_default();
+    if utils::is_stdout(&path) {
+        error!("Checkpoints require the CSV to be written to a file");
+        std::process::exit(EXIT_ERROR);
+    }
+
+    let mut checkpoint = state::DownloadCheckpoint {
+        id: id.to_string(),
+        next_offset: Some(0),
+        rows_fetched: 0,
+    };
+    if args.resume {
+        match state::load_download_checkpoint(&path) {
+            Ok(Some(saved)) if saved.id == id => {
+                info!(
+                    "Resuming download from offset {:?} ({} rows already saved)",
+                    saved.next_offset, saved.rows_fetched
+                );
+                checkpoint = saved;
+            }
+            Ok(Some(saved)) => {
+                error!("The checkpoint belongs to a different id: {}", saved.id);
+                std::process::exit(EXIT_ERROR);
+            }
+            Ok(None) => info!("No checkpoint found. Starting from the beginning..."),
+            Err(e) => {
+                error!("Error reading the checkpoint: {:?}", e);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    match checkpoint.next_offset {
+        Some(offset) => pager.resume_from(offset),
+        None => {
+            info!("The download has already completed");
+            return Ok((QueryResult::default(), checkpoint.rows_fetched as usize));
+        }
+    }
+
+    let mut res = QueryResult::default();
+    let mut is_first_page = true;
+    while let Some((_, response)) = pager.next_page().await? {
+        // rows of a resumed download are appended, without repeating the headers
+        let resumed = checkpoint.rows_fetched > 0;
+        let csv_options = utils::CsvOptions {
+            headers: !resumed,
+            append: resumed,
+            ..args.csv_options()
+        };
+        if let Err(e) = utils::save_json_as_csv(
+            &response.result.rows,
+            Some(&response.result.metadata),
+            &path,
+            &csv_options,
+        )
+        .await
+        {
+            error!("Error saving results to CSV file: {:?}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+
+        checkpoint.rows_fetched += response.result.rows.len() as u64;
+        checkpoint.next_offset = pager.next_offset();
+        if let Err(e) = state::save_download_checkpoint(&path, &checkpoint) {
+            error!("Error saving the checkpoint: {:?}", e);
+        }
+        debug!("{} rows saved to CSV", checkpoint.rows_fetched);
+
+        if is_first_page {
+            res = without_rows(response);
+            is_first_page = false;
+        }
+    }
+
+    // the download has completed, so there is nothing left to resume
+    if let Err(e) = state::remove_download_checkpoint(&path) {
+        error!("Error removing the checkpoint: {:?}", e);
+    }
+    log_saved(&path);
+
+    res.metadata.datapoint_count = pager.datapoint_count();
+    res.page_count = pager.page_count();
+    pager.log_summary();
+    Ok((res, checkpoint.rows_fetched as usize))
+}
+
+// Keeps the ids, state and metadata of the page, dropping its rows.
+fn without_rows(response: QueryResultsResponse) -> QueryResult {
+    QueryResult {
+        is_partial: response.is_partial(),
+        query_id: response.query_id,
+        execution_id: response.execution_id,
+        state: response.state,
+        metadata: response.result.metadata,
+        ..Default::default()
+    }
+}
+
+// Saves the results to CSV (or logs them), and writes the run summary if requested.
+async fn output_results(
+    client: &DuneClient,
+    res: QueryResult,
+    row_count: usize,
+    args: ResultsArgs,
+    started_at: Instant,
+) {
+    if res.is_partial {
+        warn!(
+            "Execution {} completed partially, so some rows are missing from the results",
+            res.execution_id
+        );
+        warn!(
+            "Hint: the results were truncated, e.g. because the query ran out of memory. \
+            Run it with a larger `--engine-size`, or reduce the size of its results."
+        );
+    }
+    if let Some(offset) = res.next_offset {
+        info!("More rows are available from offset {}", offset);
+    }
+
+    // a failed export doesn't prevent the other outputs, but makes the command fail once written
+    let mut failed = false;
+
+    // save results to CSV if path is provided
+    let output_path = match args.csv_path() {
+        // the pages have already been written as they arrived
+        _ if args.page_files.is_some() => args.page_files.clone(),
+        Some(path) if args.checkpoint || args.streams_csv() => Some(path),
+        Some(path) => match utils::save_json_as_csv(
+            &res.rows,
+            Some(&res.metadata),
+            &path,
+            &args.csv_options(),
+        )
+        .await
+        {
+            Ok(_) => {
+                log_saved(&path);
+                Some(path)
+            }
+            Err(e) => {
+                error!("Error saving results to CSV file: {:?}", e);
+                failed = true;
+                None
+            }
+        },
+        None if args.path_parquet.is_some()
+            || args.path_json.is_some()
+            || args.path_xlsx.is_some()
+            || args.path_sqlite.is_some() =>
+        {
+            None
+        }
+        None => {
+            let format = args.format.unwrap_or(utils::OutputFormat::Json);
+            if let Err(e) = utils::print_rows(&res.rows, &res.metadata, format, &args.csv_options())
+            {
+                error!("Error writing results to stdout: {:?}", e);
+            }
+            None
+        }
+    };
+
+    let output_path = match args.json_path() {
+        Some(path) => match utils::save_json_rows(&res.rows, &path) {
+            Ok(_) => {
+                log_saved(&path);
+                output_path.or(Some(path))
+            }
+            Err(e) => {
+                error!("Error saving results to JSON file: {:?}", e);
+                failed = true;
+                output_path
+            }
+        },
+        None => output_path,
+    };
+
+    let output_path = match &args.path_parquet {
+        Some(path) => match utils::save_json_as_parquet(&res.rows, &res.metadata, path) {
+            Ok(_) => {
+                log_saved(path);
+                output_path.or(Some(path.clone()))
+            }
+            Err(e) => {
+                error!("Error saving results to Parquet file: {:?}", e);
+                failed = true;
+                output_path
+            }
+        },
+        None => output_path,
+    };
+
+    let output_path = match &args.path_xlsx {
+        Some(path) => match utils::save_json_as_xlsx(&res.rows, &res.metadata, path) {
+            Ok(_) => {
+                log_saved(path);
+                output_path.or(Some(path.clone()))
+            }
+            Err(e) => {
+                error!("Error saving results to Excel file: {:?}", e);
+                failed = true;
+                output_path
+            }
+        },
+        None => output_path,
+    };
+
+    let output_path = match &args.path_sqlite {
+        Some(path) => {
+            let table = args
+                .sqlite_table
+                .clone()
+                .unwrap_or_else(|| format!("dune_{}", res.query_id));
+            match utils::save_json_to_sqlite(
+                &res.rows,
+                &res.metadata,
+                path,
+                &table,
+                args.sqlite_mode(),
+            ) {
+                Ok(_) => {
+                    info!("Results saved to table {} of {}", table, path);
+                    output_path.or(Some(path.clone()))
                 }
-                None => info!("Results: {:?}", res),
+                Err(e) => {
+                    error!("Error saving results to SQLite database: {:?}", e);
+                    failed = true;
+                    output_path
+                }
+            }
+        }
+        None => output_path,
+    };
+
+    if let Some(table_name) = &args.upload_as {
+        upload_results(client, &res, table_name, &args).await;
+    }
+
+    if let Some(summary_path) = args.summary_json {
+        let summary = utils::RunSummary::new(&res, row_count, started_at.elapsed(), output_path);
+        match utils::save_summary_json(&summary, &summary_path) {
+            Ok(_) => info!("Summary saved to {}", summary_path),
+            Err(e) => error!("Error saving the summary JSON file: {:?}", e),
+        }
+    }
+
+    // only fail once the outputs and the summary have been written, for the CI artifacts
+    if failed {
+        std::process::exit(EXIT_ERROR);
+    }
+    if args.fail_on_empty && row_count == 0 {
+        exit_with_error(DuneError::EmptyResults {
+            execution_id: res.execution_id,
+        });
+    }
+}
+
+// Confirms where the results were saved. Nothing is logged when they were piped to stdout.
+fn log_saved(path: &str) {
+    if !utils::is_stdout(path) {
+        info!("Results saved to {}", path);
+    }
+}
+
+// Uploads the rows as a new Dune table.
+async fn upload_results(
+    client: &DuneClient,
+    res: &QueryResult,
+    table_name: &str,
+    args: &ResultsArgs,
+) {
+    // Dune expects a comma-delimited CSV, and detects varbinary columns from their hex values
+    let csv_options = utils::CsvOptions {
+        delimiter: b',',
+        gzip: false,
+        varbinary: None,
+        excel: false,
+        ..args.csv_options()
+    };
+    let data =
+        match utils::write_json_as_csv(&res.rows, Some(&res.metadata), Vec::new(), &csv_options) {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(e) => {
+                error!("Error serializing results to CSV: {:?}", e);
+                std::process::exit(EXIT_ERROR);
+            }
+        };
+
+    let description = Some(format!("Results of query {}", res.query_id));
+    match client
+        .upload_csv(table_name, data, description, args.upload_private)
+        .await
+    {
+        Ok(upload) if upload.success => info!(
+            "Results uploaded as table: {}",
+            upload.full_name.unwrap_or_else(|| table_name.to_string())
+        ),
+        Ok(upload) => {
+            error!("Error uploading results: {:?}", upload);
+            std::process::exit(EXIT_ERROR);
+        }
+        Err(e) => exit_with_error(e),
+    }
+}
+
+// Submits a new execution of the query and persists its id, so that it can be resumed.
+// If `resume` is set and an execution was previously submitted, reattaches to it instead.
+async fn submit_or_resume_execution(
+    client: &DuneClient,
+    query_id: u64,
+    performance: EngineSize,
+    params: Option<JsonValue>,
+    resume: bool,
+) -> Result<String, DuneError> {
+    if resume {
+        match state::load_execution_state(query_id) {
+            Ok(Some(state)) => {
+                info!("Resuming query execution: {}", state.execution_id);
+                return Ok(state.execution_id);
+            }
+            Ok(None) => info!("No previous execution found. Submitting a new one..."),
+            Err(e) => error!("Error reading the execution state: {:?}", e),
+        }
+    }
+
+    let res = client.execute_query(query_id, performance, params).await?;
+    info!("Query execution successfully submitted: {:?}", res);
+    record_history(query_id, &res.execution_id, performance);
+    if let Err(e) = state::save_execution_state(&state::ExecutionState {
+        query_id,
+        execution_id: res.execution_id.clone(),
+    }) {
+        error!("Error saving the execution state: {:?}", e);
+    }
+
+    Ok(res.execution_id)
+}
+
+/// Query of the `execute-batch` file.
+#[derive(Debug, Deserialize)]
+struct BatchQuery {
+    query_id: u64,
+    engine_size: Option<EngineSize>,
+    params: Option<JsonValue>,
+}
+
+// Reads the queries of the `execute-batch` file.
+fn read_batch_file(path: &Path) -> Vec<BatchQuery> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Error reading the batch file {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    match serde_json::from_str(&contents) {
+        Ok(queries) => queries,
+        Err(e) => {
+            error!("Invalid batch file {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Clears the terminal and prints the results of a `watch` cycle as a table. When stdout isn't a
+// terminal, the tables of the successive cycles are written one after the other instead.
+fn draw_watched_results(res: &QueryResult, interval: u64) {
+    if std::io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "Query {} at {} (refreshed every {}s, Ctrl-C to stop)\n",
+            res.query_id,
+            chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            interval
+        );
+    }
+    let format = utils::OutputFormat::Table;
+    if let Err(e) = utils::print_rows(&res.rows, &res.metadata, format, &Default::default()) {
+        error!("Error printing the results: {:?}", e);
+        std::process::exit(EXIT_ERROR);
+    }
+}
+
+// Prints a response as a single-line JSON object to stdout, for scripts to consume, e.g. with `jq`.
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            error!("Error serializing the response: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Reads the SQL of a query from a file.
+fn read_sql_file(path: &Path) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(sql) => sql,
+        Err(e) => {
+            error!("Error reading the SQL file {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Returns the inline query parameters, or those read from the parameters file.
+fn resolve_params(params: Option<JsonValue>, params_file: Option<PathBuf>) -> Option<JsonValue> {
+    let path = match params_file {
+        Some(path) => path,
+        None => return params,
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!(
+                "Error reading the parameters file {}: {}",
+                path.display(),
+                e
+            );
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    match parse_params_json(&contents) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            error!("Invalid parameters file {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Parses the number of records of `--peak`, "true" being the default count and "false" (or 0)
+// disabling it.
+fn parse_peak(s: &str) -> Result<u64, String> {
+    match s.trim() {
+        "true" => Ok(10),
+        "false" => Ok(0),
+        count => count
+            .parse::<u64>()
+            .map_err(|_| format!("'{}' is neither a number of records nor true/false", count)),
+    }
+}
+
+// Parses the parameter definitions of a saved query, a JSON array of `{key, type, value}`.
+fn parse_query_parameters(s: &str) -> Result<Vec<QueryParameter>, String> {
+    serde_json::from_str(s).map_err(|e| format!("expected a JSON array of parameters: {}", e))
+}
+
+// Parses the query parameters, which Dune expects to be a JSON object.
+fn parse_params_json(contents: &str) -> Result<JsonValue, String> {
+    let params: JsonValue = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    if !params.is_object() {
+        return Err("the parameters must be a JSON object".to_string());
+    }
+    Ok(params)
+}
+
+// Parses the lower bound of `--since`, a date alone starting at midnight UTC.
+fn parse_since(s: &str) -> Result<String, String> {
+    parse_time_bound(s, 0)
+}
+
+// Parses the exclusive upper bound of `--until`, a date alone including the whole day.
+fn parse_until(s: &str) -> Result<String, String> {
+    parse_time_bound(s, 1)
+}
+
+// Parses a date or an RFC3339 datetime into the UTC timestamp format of the results, e.g.
+// `2024-09-01 00:00:00`. A date alone is moved forward by `date_offset_days`.
+fn parse_time_bound(s: &str, date_offset_days: i64) -> Result<String, String> {
+    let s = s.trim();
+    let datetime = match chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date) => (date + chrono::Duration::days(date_offset_days))
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(|| format!("invalid date: {}", s))?,
+        Err(_) => chrono::DateTime::parse_from_rfc3339(s)
+            .map_err(|e| {
+                format!(
+                    "'{}' is neither a date (YYYY-MM-DD) nor an RFC3339 datetime: {}",
+                    s, e
+                )
+            })?
+            .naive_utc(),
+    };
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S%.f").to_string())
+}
+
+// Prints the credits of each billing period, and the storage used.
+fn print_account_info(account: &AccountInfo) {
+    let headers: Vec<String> = ["period", "credits_used", "credits_included", "credits_left"]
+        .iter()
+        .map(|header| header.to_string())
+        .collect();
+    let rows: Vec<Vec<String>> = account
+        .billing_periods
+        .iter()
+        .map(|period| {
+            vec![
+                format!("{} - {}", period.start_date, period.end_date),
+                period.credits_used.to_string(),
+                period.credits_included.to_string(),
+                period.credits_remaining().to_string(),
+            ]
+        })
+        .collect();
+    print!("{}", utils::format_table(&headers, &rows));
+
+    if let (Some(used), Some(allowed)) = (account.bytes_used, account.bytes_allowed) {
+        println!("\nStorage: {} / {} bytes", used, allowed);
+    }
+    if let Some(private_queries) = account.private_queries {
+        println!("Private queries: {}", private_queries);
+    }
+    if let Some(private_dashboards) = account.private_dashboards {
+        println!("Private dashboards: {}", private_dashboards);
+    }
+}
+
+// Prints the metadata of a saved query, with its parameters as a table.
+fn print_query_metadata(metadata: &QueryMetadata) {
+    println!("Query:       {}", metadata.query_id);
+    println!("Name:        {}", metadata.name);
+    if let Some(description) = metadata.description.as_deref().filter(|d| !d.is_empty()) {
+        println!("Description: {}", description);
+    }
+    println!("Tags:        {}", metadata.tags.join(", "));
+    println!("Private:     {}", metadata.is_private);
+
+    if !metadata.parameters.is_empty() {
+        let headers: Vec<String> = ["parameter", "type", "default"]
+            .iter()
+            .map(|header| header.to_string())
+            .collect();
+        let rows: Vec<Vec<String>> = metadata
+            .parameters
+            .iter()
+            .map(|param| {
+                vec![
+                    param.key.clone(),
+                    param.param_type.clone(),
+                    match &param.value {
+                        Some(JsonValue::String(value)) => value.clone(),
+                        Some(value) => value.to_string(),
+                        None => String::new(),
+                    },
+                ]
+            })
+            .collect();
+        print!("\n{}", utils::format_table(&headers, &rows));
+    }
+}
+
+// Prints the most recent executions of the history as a table.
+fn print_history(query_id: Option<u64>, limit: usize) {
+    let entries = match history::load_history() {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error reading the history: {:?}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
+    let headers: Vec<String> = ["timestamp", "query_id", "execution_id", "engine_size"]
+        .iter()
+        .map(|header| header.to_string())
+        .collect();
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .rev()
+        .filter(|entry| query_id.is_none_or(|query_id| entry.query_id == query_id))
+        .take(limit)
+        .map(|entry| {
+            vec![
+                entry.timestamp.to_string(),
+                entry.query_id.to_string(),
+                entry.execution_id.clone(),
+                entry.engine_size.as_str().to_string(),
+            ]
+        })
+        .collect();
+    print!("{}", utils::format_table(&headers, &rows));
+}
+
+// Appends the submitted execution to the history. A failure doesn't fail the command.
+fn record_history(query_id: u64, execution_id: &str, engine_size: EngineSize) {
+    let entry = history::HistoryEntry::new(query_id, execution_id.to_string(), engine_size);
+    if let Err(e) = history::append_history(&entry) {
+        warn!("Error saving the execution to the history: {:?}", e);
+    }
+}
+
+fn new_client(
+    api_key: String,
+    base_url: Option<&str>,
+    timeout_per_page: Option<u64>,
+    dry_run: bool,
+) -> DuneClient {
+    let base_url = base_url.unwrap_or(dune_cli::client::DEFAULT_BASE_URL);
+    let client = DuneClient::with_base_url(api_key, base_url).with_dry_run(dry_run);
+    match timeout_per_page {
+        Some(secs) => client.with_page_timeout(Duration::from_secs(secs)),
+        None => client,
+    }
+}
+
+// Runs the future, bounded by the overall timeout (in seconds) if provided.
+// Fails with `DuneError::Timeout` if the timeout elapsed.
+async fn with_timeout<T, F: std::future::Future<Output = Result<T, DuneError>>>(
+    timeout: Option<u64>,
+    future: F,
+) -> Result<T, DuneError> {
+    match timeout {
+        Some(secs) => {
+            let timeout = Duration::from_secs(secs);
+            match tokio::time::timeout(timeout, future).await {
+                Ok(res) => res,
+                Err(_) => Err(DuneError::Timeout(timeout)),
             }
         }
+        None => future.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let res = with_timeout(Some(0), std::future::pending::<Result<(), DuneError>>()).await;
+        let e = res.unwrap_err();
+        assert!(matches!(e, DuneError::Timeout(timeout) if timeout == Duration::ZERO));
+        // a timed-out download must not look like a success to scripts
+        assert_eq!(exit_code(&e), EXIT_ERROR);
+
+        assert!(with_timeout(Some(60), async { Ok(()) }).await.is_ok());
+        assert!(with_timeout(None, async { Ok(()) }).await.is_ok());
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_params_json() {
+        assert_eq!(
+            parse_params_json(r#"{"min_lp_value_usd": 1000}"#).unwrap(),
+            serde_json::json!({"min_lp_value_usd": 1000})
+        );
+        assert!(parse_params_json(r#"[1, 2]"#).is_err());
+        assert!(parse_params_json("not json").is_err());
+
+        // clap would otherwise take the whole argument as a JSON string
+        let cli = Cli::try_parse_from([
+            "dune-cli",
+            "execute",
+            "--id",
+            "1",
+            "--params",
+            r#"{"a": 1}"#,
+        ])
+        .unwrap();
+        match cli.command {
+            Commands::Execute { params, .. } => {
+                assert_eq!(params, Some(serde_json::json!({"a": 1})))
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_resume_from_conflicts() {
+        let args = [
+            "dune-cli",
+            "get-results",
+            "--id",
+            "1",
+            "--path-csv",
+            "out.csv",
+        ];
+        let parse = |extra: &[&str]| Cli::try_parse_from([&args[..], extra].concat());
+        assert!(parse(&["--resume-from", "100"]).is_ok());
+        assert!(parse(&["--resume-from", "100", "--upload-as", "table"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_query_parameters() {
+        let cli = Cli::try_parse_from([
+            "dune-cli",
+            "create-query",
+            "--name",
+            "test",
+            "--sql-file",
+            "query.sql",
+            "--parameters",
+            r#"[{"key": "min_value", "type": "number", "value": "1"}, {"key": "chain", "type": "text"}]"#,
+        ])
+        .unwrap();
+        match cli.command {
+            Commands::CreateQuery { parameters, .. } => {
+                let keys: Vec<String> = parameters.unwrap().into_iter().map(|p| p.key).collect();
+                assert_eq!(keys, vec!["min_value", "chain"]);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+        assert!(parse_query_parameters(r#"{"min_value": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_time_bounds() {
+        assert_eq!(
+            parse_since("2024-09-01"),
+            Ok("2024-09-01 00:00:00".to_string())
+        );
+        assert_eq!(
+            parse_until("2024-09-30"),
+            Ok("2024-10-01 00:00:00".to_string())
+        );
+        assert_eq!(
+            parse_since("2024-09-01T12:30:00+02:00"),
+            Ok("2024-09-01 10:30:00".to_string())
+        );
+        assert_eq!(
+            parse_until("2024-09-01T12:30:00.5Z"),
+            Ok("2024-09-01 12:30:00.500".to_string())
+        );
+        assert!(parse_since("2024-09-01 12:30").is_err());
+        assert!(parse_since("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_peak() {
+        let peak = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from([&["dune-cli", "get-results", "--id", "1"], args].concat())
+                    .unwrap();
+            match cli.command {
+                Commands::GetResults { results, .. } => results.peak(),
+                command => panic!("unexpected command: {:?}", command),
+            }
+        };
+        assert_eq!(peak(&[]), None);
+        assert_eq!(peak(&["--peak"]), Some(10));
+        assert_eq!(peak(&["--peak", "25"]), Some(25));
+        assert_eq!(peak(&["--peak", "true"]), Some(10));
+        assert_eq!(peak(&["--peak", "false"]), None);
+        assert!(parse_peak("some").is_err());
+    }
+
+    #[test]
+    fn test_parse_csv_delimiter() {
+        assert_eq!(parse_csv_delimiter(","), Ok(b','));
+        assert_eq!(parse_csv_delimiter("\\t"), Ok(b'\t'));
+        assert!(parse_csv_delimiter(";;").is_err());
+        assert!(parse_csv_delimiter("é").is_err());
     }
 }
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+/// Directory, relative to the working directory, where the execution state is persisted.
+const STATE_DIR: &str = ".dune-cli-state";
+
+/// Submitted execution of a query, persisted so that it can be resumed after a restart.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecutionState {
+    pub query_id: u64,
+    pub execution_id: String,
+}
+
+fn execution_state_path(query_id: u64) -> PathBuf {
+    PathBuf::from(STATE_DIR).join(format!("{}.json", query_id))
+}
+
+pub fn save_execution_state(state: &ExecutionState) -> io::Result<()> {
+    fs::create_dir_all(STATE_DIR)?;
+    fs::write(
+        execution_state_path(state.query_id),
+        serde_json::to_string_pretty(state)?,
+    )
+}
+
+/// Loads the persisted execution of the query, if any.
+pub fn load_execution_state(query_id: u64) -> io::Result<Option<ExecutionState>> {
+    match fs::read_to_string(execution_state_path(query_id)) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn remove_execution_state(query_id: u64) -> io::Result<()> {
+    match fs::remove_file(execution_state_path(query_id)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Progress of a `--checkpoint` download, persisted next to the CSV file after every page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadCheckpoint {
+    /// Query or execution id whose results are being downloaded.
+    pub id: String,
+    /// Offset of the next page to fetch, `None` once the download has completed.
+    pub next_offset: Option<u64>,
+    pub rows_fetched: u64,
+}
+
+fn download_checkpoint_path(csv_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.checkpoint.json", csv_path))
+}
+
+pub fn save_download_checkpoint(csv_path: &str, checkpoint: &DownloadCheckpoint) -> io::Result<()> {
+    fs::write(
+        download_checkpoint_path(csv_path),
+        serde_json::to_string_pretty(checkpoint)?,
+    )
+}
+
+/// Loads the checkpoint of the download into `csv_path`, if any.
+pub fn load_download_checkpoint(csv_path: &str) -> io::Result<Option<DownloadCheckpoint>> {
+    match fs::read_to_string(download_checkpoint_path(csv_path)) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn remove_download_checkpoint(csv_path: &str) -> io::Result<()> {
+    match fs::remove_file(download_checkpoint_path(csv_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
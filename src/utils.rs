@@ -1,52 +1,1232 @@
-use csv::WriterBuilder;
+use arrow_array::{
+    ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::ValueEnum;
+use csv::{Terminator, WriterBuilder};
+use dune_cli::{
+    schema::{self, decode_hex, ColumnType, SqlDialect, TypedValue},
+    types::{QueryResult, QueryResultMetadata},
+};
+use flate2::{write::GzEncoder, Compression};
+use parquet::{
+    arrow::ArrowWriter, basic::Compression as ParquetCompression,
+    file::properties::WriterProperties,
+};
+use rusqlite::{types::Value as SqliteValue, Connection};
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use serde::Serialize;
 use serde_json::Value as JsonValue;
-use std::error::Error;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    sync::Arc,
+    time::Duration,
+};
+use tiny_keccak::{Hasher, Keccak};
+use tracing::warn;
 
+/// Client-side order of the exported columns.
+#[derive(Debug, Clone)]
+pub struct ColumnOrder {
+    pub columns: Vec<String>,
+    /// Whether the columns that aren't listed are dropped, instead of appended after the listed ones.
+    pub drop_unlisted: bool,
+}
+
+impl ColumnOrder {
+    /// Reorders the headers. Listed columns that aren't part of the headers are ignored.
+    pub fn apply(&self, headers: Vec<String>) -> Vec<String> {
+        let mut ordered: Vec<String> = Vec::new();
+        for column in &self.columns {
+            if headers.contains(column) {
+                ordered.push(column.clone());
+            } else {
+                warn!("Column '{}' not found in the results", column);
+            }
+        }
+
+        if !self.drop_unlisted {
+            ordered.extend(
+                headers
+                    .into_iter()
+                    .filter(|header| !self.columns.contains(header)),
+            );
+        }
+        ordered
+    }
+}
+
+/// Options of the CSV export.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Whether to write the header row.
+    pub headers: bool,
+    pub column_order: Option<ColumnOrder>,
+    /// Whether to gzip-compress the output.
+    pub gzip: bool,
+    /// Whether to append to the file, instead of overwriting it.
+    pub append: bool,
+    /// Encoding of the `varbinary` columns. Left as returned by Dune (`0x`-prefixed hex) if unset.
+    pub varbinary: Option<VarbinaryEncoding>,
+    /// Decimals of the `double` columns. Left as returned by Dune if unset.
+    pub float_precision: Option<usize>,
+    /// Whether to start the file with a UTF-8 BOM and end the lines with CRLF, so that Excel
+    /// reads the non-ASCII characters correctly.
+    pub excel: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b';',
+            headers: true,
+            column_order: None,
+            gzip: false,
+            append: false,
+            varbinary: None,
+            float_precision: None,
+            excel: false,
+        }
+    }
+}
+
+/// Encoding of the `varbinary` columns in the CSV output.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum VarbinaryEncoding {
+    /// Lowercase `0x`-prefixed hex.
+    Hex,
+    /// EIP-55 checksummed addresses. Values that aren't 20 bytes long are written as hex.
+    Checksum,
+    /// Standard base64 of the raw bytes.
+    Base64,
+}
+
+impl VarbinaryEncoding {
+    /// Re-encodes the `0x`-prefixed hex value. Values that aren't valid hex are returned as is.
+    pub fn encode(&self, value: &str) -> String {
+        let Some(bytes) = decode_hex(value) else {
+            return value.to_string();
+        };
+        match self {
+            VarbinaryEncoding::Hex => format!("0x{}", encode_hex(&bytes)),
+            VarbinaryEncoding::Checksum if bytes.len() == 20 => checksum_address(&bytes),
+            VarbinaryEncoding::Checksum => format!("0x{}", encode_hex(&bytes)),
+            VarbinaryEncoding::Base64 => BASE64_STANDARD.encode(&bytes),
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// EIP-55: each hex letter is uppercased if the matching nibble of the keccak-256 hash of the
+// lowercase hex address is 8 or more.
+fn checksum_address(bytes: &[u8]) -> String {
+    let hex = encode_hex(bytes);
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(hex.as_bytes());
+    keccak.finalize(&mut hash);
+
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+            match nibble >= 8 {
+                true => c.to_ascii_uppercase(),
+                false => c,
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// Path that writes the output to stdout instead of a file.
+pub const STDOUT_PATH: &str = "-";
+
+/// Whether the path means stdout: [`STDOUT_PATH`] or an empty path.
+pub fn is_stdout(path: &str) -> bool {
+    path.is_empty() || path == STDOUT_PATH
+}
+
+/// Number of leading rows whose keys make up the CSV headers, so that columns missing
+/// from the first rows still get a header.
+const HEADER_SAMPLE_SIZE: usize = 100;
+
+/// Byte order mark written at the start of the CSV output for Excel.
+const UTF8_BOM: &str = "\u{FEFF}";
+
+/// Number of rows written between flushes of the CSV output.
+const FLUSH_INTERVAL: usize = 10_000;
+
+/// Saves the records as CSV. See [`write_json_as_csv`] for how the headers are chosen.
 pub async fn save_json_as_csv(
-    records: Vec<JsonValue>,
+    records: &[JsonValue],
+    metadata: Option<&QueryResultMetadata>,
+    csv_file_path: &str,
+    options: &CsvOptions,
+) -> Result<(), Box<dyn Error>> {
+    let target = open_csv_target(csv_file_path, options)?;
+    write_json_as_csv(records, metadata, target, options)?.finish()?;
+    Ok(())
+}
+
+/// Opens the CSV output: stdout for `-` or an empty path, otherwise the file at the given path.
+pub fn open_csv_target(
     csv_file_path: &str,
+    options: &CsvOptions,
+) -> io::Result<CsvTarget<Box<dyn Write>>> {
+    let writer: Box<dyn Write> = match csv_file_path {
+        path if is_stdout(path) => Box::new(io::stdout().lock()),
+        path => Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(options.append)
+                .truncate(!options.append)
+                .open(path)?,
+        ),
+    };
+    Ok(CsvTarget::new(writer, options.gzip))
+}
+
+fn write_csv_target<W: Write>(
+    records: &[JsonValue],
+    metadata: Option<&QueryResultMetadata>,
+    target: W,
+    options: &CsvOptions,
 ) -> Result<(), Box<dyn Error>> {
-    // Create a CSV writer
-    let mut wtr = WriterBuilder::new()
-        .delimiter(b';')
-        .from_path(csv_file_path)?;
+    write_json_as_csv(
+        records,
+        metadata,
+        CsvTarget::new(target, options.gzip),
+        options,
+    )?
+    .finish()?;
+    Ok(())
+}
+
+/// Destination of the CSV output, optionally gzip-compressed.
+pub enum CsvTarget<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
 
-    // Initialize headers
-    let mut headers: Vec<String> = Vec::new();
+impl<W: Write> CsvTarget<W> {
+    pub fn new(writer: W, gzip: bool) -> Self {
+        match gzip {
+            true => CsvTarget::Gzip(GzEncoder::new(writer, Compression::default())),
+            false => CsvTarget::Plain(writer),
+        }
+    }
+
+    /// Finishes the output. The gzip stream is finished explicitly, so that errors writing
+    /// its trailer aren't lost.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CsvTarget::Plain(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            CsvTarget::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CsvTarget<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CsvTarget::Plain(writer) => writer.write(buf),
+            CsvTarget::Gzip(encoder) => encoder.write(buf),
+        }
+    }
 
-    // Write headers
-    if let Some(first_record) = records.get(0) {
-        if let Some(object) = first_record.as_object() {
-            // Write headers based on the keys of the first object
-            headers = object.keys().cloned().collect();
-            wtr.write_record(&headers)?;
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CsvTarget::Plain(writer) => writer.flush(),
+            CsvTarget::Gzip(encoder) => encoder.flush(),
         }
     }
+}
 
-    // Write the records to the CSV file
+/// Writes the records as CSV into the writer, returning the writer once flushed.
+///
+/// The headers are the result metadata's `column_names`, falling back to the union of the
+/// keys of all the records.
+pub fn write_json_as_csv<W: Write>(
+    records: &[JsonValue],
+    metadata: Option<&QueryResultMetadata>,
+    writer: W,
+    options: &CsvOptions,
+) -> Result<W, Box<dyn Error>> {
+    let mut csv_writer = CsvStreamWriter::new(writer, options);
+    match metadata {
+        Some(metadata) => csv_writer.set_metadata(metadata),
+        None => csv_writer.set_column_names(&key_union(records)),
+    }
     for record in records {
-        if let Some(object) = record.as_object() {
-            let row: Vec<String> = headers
-                .iter()
-                .map(|key| {
-                    // Get the value for the current key and convert it to a string
-                    match object.get(key) {
-                        Some(value) => match value {
-                            JsonValue::String(s) => s.clone(),
-                            JsonValue::Number(n) => n.to_string(),
-                            JsonValue::Bool(b) => b.to_string(),
-                            JsonValue::Null => "".to_string(),
-                            _ => "".to_string(), // Handle other types if necessary
-                        },
-                        None => "".to_string(), // Key not found
+        csv_writer.write_row(record)?;
+    }
+    csv_writer.finish()
+}
+
+/// Writes JSON rows as CSV one at a time, so that the rows don't have to be held in memory.
+///
+/// The headers are the column names set with [`CsvStreamWriter::set_column_names`], or
+/// otherwise the union of the keys of the first rows, which are buffered until then.
+pub struct CsvStreamWriter<W: Write> {
+    writer: csv::Writer<W>,
+    options: CsvOptions,
+    column_names: Option<Vec<String>>,
+    /// Types of the columns, from the result metadata, which decide how their values are written.
+    column_types: HashMap<String, ColumnType>,
+    headers: Option<Vec<String>>,
+    sample: Vec<JsonValue>,
+    rows_written: usize,
+}
+
+impl<W: Write> CsvStreamWriter<W> {
+    pub fn new(writer: W, options: &CsvOptions) -> Self {
+        Self {
+            writer: WriterBuilder::new()
+                .delimiter(options.delimiter)
+                .terminator(match options.excel {
+                    true => Terminator::CRLF,
+                    false => Terminator::Any(b'\n'),
+                })
+                .from_writer(writer),
+            options: options.clone(),
+            column_names: None,
+            column_types: HashMap::new(),
+            headers: None,
+            sample: Vec::new(),
+            rows_written: 0,
+        }
+    }
+
+    /// Uses the column names, in order, as the headers instead of the keys of the rows.
+    /// Has no effect once rows have been written, nor if the column names are empty.
+    pub fn set_column_names(&mut self, column_names: &[String]) {
+        if self.headers.is_none() && !column_names.is_empty() {
+            self.column_names = Some(column_names.to_vec());
+        }
+    }
+
+    /// Uses the result metadata's column names as the headers, and its column types to format
+    /// the `varbinary` and numeric columns.
+    pub fn set_metadata(&mut self, metadata: &QueryResultMetadata) {
+        self.set_column_names(&metadata.column_names);
+        self.column_types = metadata
+            .column_names
+            .iter()
+            .zip(&metadata.column_types)
+            .map(|(name, column_type)| (name.clone(), ColumnType::from(column_type.as_str())))
+            .collect();
+    }
+
+    pub fn write_row(&mut self, row: &JsonValue) -> Result<(), Box<dyn Error>> {
+        if self.headers.is_some() {
+            return self.write_record(row);
+        }
+
+        self.sample.push(row.clone());
+        if self.column_names.is_some() || self.sample.len() >= HEADER_SAMPLE_SIZE {
+            self.write_sample()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows and flushes the output, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        if self.headers.is_none() {
+            self.write_sample()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer.into_inner().map_err(|e| e.into_error())?)
+    }
+
+    // Settles the headers, then writes them along with the buffered rows.
+    fn write_sample(&mut self) -> Result<(), Box<dyn Error>> {
+        // without rows nor column names, there's nothing to write a header for
+        let has_columns = self.column_names.is_some() || !self.sample.is_empty();
+        let mut headers = match self.column_names.take() {
+            Some(column_names) => column_names,
+            None => key_union(&self.sample),
+        };
+        if let Some(column_order) = &self.options.column_order {
+            headers = column_order.apply(headers);
+        }
+        if self.options.headers && has_columns {
+            // the BOM goes with the header row, so it isn't repeated in the middle of an
+            // appended file
+            let bom = match self.options.excel {
+                true => UTF8_BOM,
+                false => "",
+            };
+            let mut header_row = headers.clone();
+            if let Some(first) = header_row.first_mut() {
+                first.insert_str(0, bom);
+            }
+            self.writer.write_record(&header_row)?;
+        }
+        self.headers = Some(headers);
+
+        for row in std::mem::take(&mut self.sample) {
+            self.write_record(&row)?;
+        }
+        Ok(())
+    }
+
+    fn write_record(&mut self, row: &JsonValue) -> Result<(), Box<dyn Error>> {
+        let (Some(headers), Some(object)) = (&self.headers, row.as_object()) else {
+            return Ok(());
+        };
+        let record: Vec<String> = headers
+            .iter()
+            .map(|key| match (object.get(key), self.column_types.get(key)) {
+                (Some(JsonValue::String(value)), Some(ColumnType::Varbinary)) => {
+                    match self.options.varbinary {
+                        Some(encoding) => encoding.encode(value),
+                        None => value.clone(),
                     }
+                }
+                (Some(JsonValue::Number(n)), Some(column_type)) => {
+                    number_cell(n, column_type, self.options.float_precision)
+                }
+                (value, _) => value.map(cell_value).unwrap_or_default(),
+            })
+            .collect();
+        self.writer.write_record(&record)?;
+
+        self.rows_written += 1;
+        if self.rows_written.is_multiple_of(FLUSH_INTERVAL) {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Saves the rows as a Parquet file, with the column types taken from the result metadata.
+pub fn save_json_as_parquet(
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    write_json_as_parquet(rows, metadata, File::create(path)?)?;
+    Ok(())
+}
+
+/// Maximum rows of an Excel worksheet, including the header row.
+const XLSX_MAX_ROWS: usize = 1_048_576;
+
+/// Largest integer that Excel, which stores numbers as doubles, represents exactly.
+const XLSX_MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Saves the rows as an Excel workbook, with a header row and typed cells (numbers and booleans
+/// aren't written as text). Rows beyond the worksheet limit are dropped with a warning.
+pub fn save_json_as_xlsx(
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    write_xlsx_sheet(workbook.add_worksheet(), rows, metadata, XLSX_MAX_ROWS - 1)?;
+    workbook.save(path)?;
+    Ok(())
+}
+
+// Writes the header and at most `max_rows` rows to the worksheet.
+fn write_xlsx_sheet(
+    sheet: &mut Worksheet,
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    max_rows: usize,
+) -> Result<(), XlsxError> {
+    let columns: Vec<(String, ColumnType)> = match metadata.column_names.is_empty() {
+        false => metadata
+            .column_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let column_type = metadata.column_types.get(i).map_or("", String::as_str);
+                (name.clone(), ColumnType::from(column_type))
+            })
+            .collect(),
+        true => key_union(rows)
+            .into_iter()
+            .map(|name| (name, ColumnType::Varchar))
+            .collect(),
+    };
+
+    let header_format = Format::new().set_bold();
+    for (col, (name, _)) in columns.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, name, &header_format)?;
+    }
+
+    if rows.len() > max_rows {
+        warn!(
+            "{} rows exceed the Excel limit, so only the first {} are saved",
+            rows.len(),
+            max_rows
+        );
+    }
+    for (i, row) in rows.iter().take(max_rows).enumerate() {
+        let row_index = i as u32 + 1;
+        for (col, (name, column_type)) in columns.iter().enumerate() {
+            let value = row.get(name).unwrap_or(&JsonValue::Null);
+            let col = col as u16;
+            match TypedValue::from_json(value, column_type) {
+                TypedValue::Null => continue,
+                TypedValue::Bool(b) => sheet.write_boolean(row_index, col, b)?,
+                // larger integers would be rounded, so they are kept as text
+                TypedValue::Int(n) if n.unsigned_abs() <= XLSX_MAX_SAFE_INTEGER => {
+                    sheet.write_number(row_index, col, n as f64)?
+                }
+                TypedValue::Int(n) => sheet.write_string(row_index, col, n.to_string())?,
+                TypedValue::Double(n) => sheet.write_number(row_index, col, n)?,
+                TypedValue::Bytes(bytes) => {
+                    sheet.write_string(row_index, col, format!("0x{}", encode_hex(&bytes)))?
+                }
+                TypedValue::Text(s) | TypedValue::Timestamp(s) => {
+                    sheet.write_string(row_index, col, s)?
+                }
+            };
+        }
+    }
+    Ok(())
+}
+
+/// How the rows are saved when the SQLite table already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqliteMode {
+    /// Fail, so that no table is overwritten by mistake.
+    Create,
+    /// Drop the table and create it again.
+    Replace,
+    /// Insert the rows into the table.
+    Append,
+}
+
+/// Saves the rows into a SQLite table, created from the column names and types of the result
+/// metadata. The rows are inserted in a single transaction, so a failed run saves nothing.
+pub fn save_json_to_sqlite(
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    path: &str,
+    table: &str,
+    mode: SqliteMode,
+) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(path)?;
+    write_json_to_sqlite(&mut connection, rows, metadata, table, mode)?;
+    Ok(())
+}
+
+fn write_json_to_sqlite(
+    connection: &mut Connection,
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    table: &str,
+    mode: SqliteMode,
+) -> rusqlite::Result<()> {
+    let (column_names, column_types) = match metadata.column_names.is_empty() {
+        false => (metadata.column_names.clone(), metadata.column_types.clone()),
+        true => {
+            let names = key_union(rows);
+            let types = vec!["varchar".to_string(); names.len()];
+            (names, types)
+        }
+    };
+    let columns: Vec<ColumnType> = (0..column_names.len())
+        .map(|i| ColumnType::from(column_types.get(i).map_or("", String::as_str)))
+        .collect();
+
+    let transaction = connection.transaction()?;
+    let ddl = schema::create_table_ddl(table, &column_names, &column_types, SqlDialect::Sqlite);
+    match mode {
+        SqliteMode::Create => transaction.execute(&ddl, [])?,
+        SqliteMode::Replace => {
+            transaction.execute(
+                &format!("DROP TABLE IF EXISTS {}", schema::quote_identifier(table)),
+                [],
+            )?;
+            transaction.execute(&ddl, [])?
+        }
+        SqliteMode::Append => transaction.execute(
+            &ddl.replacen("CREATE TABLE", "CREATE TABLE IF NOT EXISTS", 1),
+            [],
+        )?,
+    };
+
+    {
+        let mut statement = transaction.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            schema::quote_identifier(table),
+            column_names
+                .iter()
+                .map(|name| schema::quote_identifier(name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; column_names.len()].join(", ")
+        ))?;
+        for row in rows {
+            let values = column_names
+                .iter()
+                .zip(&columns)
+                .map(|(name, column_type)| {
+                    let value = row.get(name).unwrap_or(&JsonValue::Null);
+                    sqlite_value(TypedValue::from_json(value, column_type))
+                });
+            statement.execute(rusqlite::params_from_iter(values))?;
+        }
+    }
+    transaction.commit()
+}
+
+fn sqlite_value(value: TypedValue) -> SqliteValue {
+    match value {
+        TypedValue::Null => SqliteValue::Null,
+        TypedValue::Bool(b) => SqliteValue::Integer(b.into()),
+        TypedValue::Int(n) => SqliteValue::Integer(n),
+        TypedValue::Double(n) => SqliteValue::Real(n),
+        TypedValue::Bytes(bytes) => SqliteValue::Blob(bytes),
+        TypedValue::Text(s) | TypedValue::Timestamp(s) => SqliteValue::Text(s),
+    }
+}
+
+/// Saves the rows as a pretty-printed JSON array, keeping nested values and types as returned.
+pub fn save_json_rows(rows: &[JsonValue], path: &str) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, rows)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the rows as Parquet into the writer, returning the writer once closed.
+///
+/// Booleans, integers and doubles keep their type, every other column is written as UTF-8.
+/// Without column names in the metadata, the columns are the keys of the rows.
+pub fn write_json_as_parquet<W: Write + Send>(
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    writer: W,
+) -> Result<W, Box<dyn Error>> {
+    let columns: Vec<(String, DataType)> = match metadata.column_names.is_empty() {
+        false => metadata
+            .column_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let column_type = metadata.column_types.get(i).map_or("", String::as_str);
+                (name.clone(), arrow_type(&ColumnType::from(column_type)))
+            })
+            .collect(),
+        true => key_union(rows)
+            .into_iter()
+            .map(|name| (name, DataType::Utf8))
+            .collect(),
+    };
+
+    let schema = Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|(name, data_type)| Field::new(name, data_type.clone(), true))
+            .collect::<Vec<Field>>(),
+    ));
+    let arrays: Vec<ArrayRef> = columns
+        .iter()
+        .map(|(name, data_type)| arrow_array(rows, name, data_type))
+        .collect();
+
+    let properties = WriterProperties::builder()
+        .set_compression(ParquetCompression::SNAPPY)
+        .build();
+    let mut parquet_writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+    parquet_writer.write(&RecordBatch::try_new(schema, arrays)?)?;
+    Ok(parquet_writer.into_inner()?)
+}
+
+fn arrow_type(column_type: &ColumnType) -> DataType {
+    match column_type {
+        ColumnType::Boolean => DataType::Boolean,
+        ColumnType::Integer | ColumnType::BigInt => DataType::Int64,
+        ColumnType::Double => DataType::Float64,
+        ColumnType::Varbinary => DataType::Binary,
+        // decimals and 256-bit integers are kept as text, so that no precision is lost
+        _ => DataType::Utf8,
+    }
+}
+
+// Builds the column's array, with missing values and values of another type as nulls.
+fn arrow_array(rows: &[JsonValue], name: &str, data_type: &DataType) -> ArrayRef {
+    let values = rows.iter().map(|row| row.get(name));
+    match data_type {
+        DataType::Boolean => Arc::new(
+            values
+                .map(|value| value.and_then(JsonValue::as_bool))
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Int64 => Arc::new(
+            values
+                .map(|value| value.and_then(JsonValue::as_i64))
+                .collect::<Int64Array>(),
+        ),
+        DataType::Float64 => Arc::new(
+            values
+                .map(|value| value.and_then(JsonValue::as_f64))
+                .collect::<Float64Array>(),
+        ),
+        DataType::Binary => Arc::new(
+            values
+                .map(|value| value.and_then(JsonValue::as_str).and_then(decode_hex))
+                .collect::<BinaryArray>(),
+        ),
+        _ => Arc::new(
+            values
+                .map(|value| match value {
+                    None | Some(JsonValue::Null) => None,
+                    Some(JsonValue::String(s)) => Some(s.clone()),
+                    Some(value) => Some(value.to_string()),
+                })
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+// Collects the keys of the rows, in order of appearance.
+fn key_union(rows: &[JsonValue]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for row in rows {
+        if let Some(object) = row.as_object() {
+            for key in object.keys() {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+    }
+    keys
+}
+
+// Formats a number according to the type of its column: integers that were parsed as floats
+// (e.g. beyond 2^64) without an exponent nor a fractional part, and doubles with the given
+// number of decimals, if any.
+fn number_cell(
+    n: &serde_json::Number,
+    column_type: &ColumnType,
+    float_precision: Option<usize>,
+) -> String {
+    match (column_type, n.as_f64()) {
+        (
+            ColumnType::Integer | ColumnType::BigInt | ColumnType::Int256 | ColumnType::UInt256,
+            Some(value),
+        ) if n.is_f64() && value.fract() == 0.0 => format!("{:.0}", value),
+        (ColumnType::Double, Some(value)) => match float_precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => n.to_string(),
+        },
+        _ => n.to_string(),
+    }
+}
+
+// Converts a JSON value into the text of a CSV or table cell.
+fn cell_value(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::Bool(b) => b.to_string(),
+        JsonValue::Null => "".to_string(),
+        // struct and array columns are kept as compact JSON
+        JsonValue::Array(_) | JsonValue::Object(_) => value.to_string(),
+    }
+}
+
+/// Output format of the results written to stdout.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON array of the rows.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    Csv,
+    /// Aligned table, for reading in a terminal.
+    Table,
+}
+
+/// Writes the rows to stdout in the given format.
+///
+/// The table columns are the given column names, reordered by the CSV options' column order.
+pub fn print_rows(
+    rows: &[JsonValue],
+    metadata: &QueryResultMetadata,
+    format: OutputFormat,
+    csv_options: &CsvOptions,
+) -> Result<(), Box<dyn Error>> {
+    let column_names = &metadata.column_names;
+    let mut stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, rows)?;
+            writeln!(stdout)?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut stdout, row)?;
+                writeln!(stdout)?;
+            }
+        }
+        OutputFormat::Csv => write_csv_target(rows, Some(metadata), stdout, csv_options)?,
+        OutputFormat::Table => {
+            let headers = match &csv_options.column_order {
+                Some(column_order) => column_order.apply(column_names.to_vec()),
+                None => column_names.to_vec(),
+            };
+            let cells: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| {
+                    headers
+                        .iter()
+                        .map(|key| row.get(key).map(cell_value).unwrap_or_default())
+                        .collect()
                 })
                 .collect();
-            wtr.write_record(&row)?;
+            write!(stdout, "{}", format_table(&headers, &cells))?;
         }
     }
+    Ok(())
+}
+
+/// Formats the rows as a table, aligning each column to its widest cell.
+pub fn format_table(headers: &[String], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .chain(std::iter::once(header))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut table = String::new();
+    for row in std::iter::once(headers).chain(rows.iter().map(Vec::as_slice)) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Formats the column names and types as an aligned two-column table.
+pub fn format_schema_table(column_names: &[String], column_types: &[String]) -> String {
+    let rows: Vec<Vec<String>> = column_names
+        .iter()
+        .zip(column_types)
+        .map(|(name, column_type)| vec![name.clone(), column_type.clone()])
+        .collect();
+    format_table(&["column".to_string(), "type".to_string()], &rows)
+}
 
-    // Flush and finalize the CSV writer
-    wtr.flush()?;
+/// Formats a duration in milliseconds for humans, e.g. `18m 42.1s`.
+pub fn format_millis(millis: u64) -> String {
+    let minutes = millis / 60_000;
+    let seconds = (millis % 60_000) as f64 / 1000.0;
+    match minutes {
+        0 => format!("{:.1}s", seconds),
+        minutes => format!("{}m {:.1}s", minutes, seconds),
+    }
+}
+
+/// Machine-readable summary of a results command, written with `--summary-json`.
+///
+/// The field names are part of the CLI's output format, keep them stable.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub query_id: u64,
+    pub execution_id: String,
+    /// Execution state reported by the API, e.g. `QUERY_STATE_COMPLETED`.
+    pub status: String,
+    /// Number of rows retrieved.
+    pub row_count: usize,
+    /// Number of datapoints reported in the result metadata.
+    pub datapoint_count: u128,
+    /// Number of pages fetched.
+    pub page_count: u64,
+    /// Wall-clock duration of the command, in seconds.
+    pub duration_secs: f64,
+    /// Path where the results were saved, if any.
+    pub output_path: Option<String>,
+}
+
+impl RunSummary {
+    pub fn new(
+        res: &QueryResult,
+        row_count: usize,
+        duration: Duration,
+        output_path: Option<String>,
+    ) -> Self {
+        Self {
+            query_id: res.query_id,
+            execution_id: res.execution_id.clone(),
+            status: res.state.clone(),
+            row_count,
+            datapoint_count: res.metadata.datapoint_count,
+            page_count: res.page_count,
+            duration_secs: duration.as_secs_f64(),
+            output_path,
+        }
+    }
+}
+
+pub fn save_summary_json(summary: &RunSummary, path: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, serde_json::to_string_pretty(summary)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use serde_json::json;
+    use std::io::Read;
+    use tracing_test::traced_test;
+
+    fn headers() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    // Rows with a column of each of the main types, and a null.
+    fn typed_rows() -> (Vec<JsonValue>, QueryResultMetadata) {
+        let rows = vec![
+            json!({ "address": "0x01", "balance": 1.5, "txs": 3 }),
+            json!({ "address": "0x02", "balance": null, "txs": 4 }),
+        ];
+        let metadata = QueryResultMetadata {
+            column_names: vec!["address".into(), "balance".into(), "txs".into()],
+            column_types: vec!["varbinary".into(), "double".into(), "bigint".into()],
+            ..Default::default()
+        };
+        (rows, metadata)
+    }
+
+    #[test]
+    fn test_column_order_appends_unlisted() {
+        let column_order = ColumnOrder {
+            columns: vec!["c".to_string(), "a".to_string()],
+            drop_unlisted: false,
+        };
+        assert_eq!(column_order.apply(headers()), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_column_order_drops_unlisted() {
+        let column_order = ColumnOrder {
+            columns: vec!["c".to_string(), "missing".to_string(), "a".to_string()],
+            drop_unlisted: true,
+        };
+        assert_eq!(column_order.apply(headers()), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_format_table() {
+        let headers = vec!["name".to_string(), "balance".to_string()];
+        let rows = vec![
+            vec!["vitalik".to_string(), "1".to_string()],
+            vec!["bob".to_string(), "".to_string()],
+        ];
+        assert_eq!(
+            format_table(&headers, &rows),
+            "name     balance\nvitalik  1\nbob\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_headers_from_sampled_rows() {
+        let records = vec![json!({ "a": 1 }), json!({ "a": 2, "b": "y" })];
+        let csv = write_json_as_csv(&records, None, Vec::new(), &CsvOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "a;b\n1;\n2;y\n");
+
+        let mut writer = CsvStreamWriter::new(Vec::new(), &CsvOptions::default());
+        for record in &records {
+            writer.write_row(record).unwrap();
+        }
+        let csv = writer.finish().unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "a;b\n1;\n2;y\n");
+    }
+
+    #[test]
+    fn test_csv_headers_from_column_names() {
+        let records = vec![json!({ "a": 1, "b": "x" })];
+        let metadata = QueryResultMetadata {
+            column_names: vec!["b".to_string(), "a".to_string(), "c".to_string()],
+            ..Default::default()
+        };
+        let csv = write_json_as_csv(
+            &records,
+            Some(&metadata),
+            Vec::new(),
+            &CsvOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "b;a;c\nx;1;\n");
+    }
+
+    #[test]
+    fn test_csv_varbinary_encoding() {
+        let records = vec![json!({
+            "address": "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "data": "0x00ff"
+        })];
+        let metadata = QueryResultMetadata {
+            column_names: vec!["address".to_string(), "data".to_string()],
+            column_types: vec!["varbinary".to_string(), "varbinary".to_string()],
+            ..Default::default()
+        };
+        let csv = |varbinary| {
+            let options = CsvOptions {
+                varbinary,
+                ..Default::default()
+            };
+            let csv = write_json_as_csv(&records, Some(&metadata), Vec::new(), &options).unwrap();
+            String::from_utf8(csv).unwrap()
+        };
+
+        assert_eq!(
+            csv(Some(VarbinaryEncoding::Checksum)),
+            "address;data\n0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed;0x00ff\n"
+        );
+        assert_eq!(
+            csv(Some(VarbinaryEncoding::Base64)),
+            "address;data\nWq62BT8+lMm5oJ8zZpQ15+8b6u0=;AP8=\n"
+        );
+        assert_eq!(
+            csv(None),
+            "address;data\n0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed;0x00ff\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_numeric_columns() {
+        let records = vec![json!({ "amount": 1.0e18, "big": 1.0e21, "price": 1234.56789 })];
+        let metadata = QueryResultMetadata {
+            column_names: vec!["amount".to_string(), "big".to_string(), "price".to_string()],
+            column_types: vec![
+                "bigint".to_string(),
+                "uint256".to_string(),
+                "double".to_string(),
+            ],
+            ..Default::default()
+        };
+        let csv = |float_precision| {
+            let options = CsvOptions {
+                float_precision,
+                ..Default::default()
+            };
+            let csv = write_json_as_csv(&records, Some(&metadata), Vec::new(), &options).unwrap();
+            String::from_utf8(csv).unwrap()
+        };
+
+        assert_eq!(
+            csv(None),
+            "amount;big;price\n1000000000000000000;1000000000000000000000;1234.56789\n"
+        );
+        assert_eq!(
+            csv(Some(2)),
+            "amount;big;price\n1000000000000000000;1000000000000000000000;1234.57\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_excel() {
+        let records = vec![json!({ "symbol": "€URC", "value": 1 })];
+        let csv = |append: bool| {
+            let options = CsvOptions {
+                excel: true,
+                headers: !append,
+                append,
+                ..Default::default()
+            };
+            write_json_as_csv(&records, None, Vec::new(), &options).unwrap()
+        };
+
+        assert_eq!(
+            csv(false),
+            b"\xEF\xBB\xBFsymbol;value\r\n\xE2\x82\xACURC;1\r\n".to_vec()
+        );
+        assert_eq!(csv(true), b"\xE2\x82\xACURC;1\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_csv_nested_values() {
+        let records = vec![json!({ "a": 1, "b": { "x": [1, 2] }, "c": ["y"] })];
+        let options = CsvOptions {
+            delimiter: b',',
+            ..Default::default()
+        };
+        let csv = write_json_as_csv(&records, None, Vec::new(), &options).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "a,b,c\n1,\"{\"\"x\"\":[1,2]}\",\"[\"\"y\"\"]\"\n"
+        );
+    }
+
+    #[test]
+    fn test_format_millis() {
+        assert_eq!(format_millis(1614), "1.6s");
+        assert_eq!(format_millis(1122148), "18m 42.1s");
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        use arrow_array::Array;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let (rows, metadata) = typed_rows();
+        let path = std::env::temp_dir().join(format!("dune-cli-{}.parquet", std::process::id()));
+        save_json_as_parquet(&rows, &metadata, &path.to_string_lossy()).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let batch = &batches[0];
+        let data_types: Vec<&DataType> = batch
+            .schema_ref()
+            .fields()
+            .iter()
+            .map(|field| field.data_type())
+            .collect();
+        assert_eq!(
+            data_types,
+            [&DataType::Binary, &DataType::Float64, &DataType::Int64]
+        );
+        let column = |name| batch.column_by_name(name).unwrap();
+        let address = column("address")
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .unwrap();
+        assert_eq!(
+            (address.value(0), address.value(1)),
+            (&[0x01][..], &[0x02][..])
+        );
+        let balance = column("balance")
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(balance.value(0), 1.5);
+        assert!(balance.is_null(1));
+        let txs = column("txs").as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(txs.values(), &[3, 4]);
+        assert_eq!(
+            arrow_type(&ColumnType::from("decimal(38,0)")),
+            DataType::Utf8
+        );
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_write_xlsx() {
+        use calamine::{Data, Reader, Xlsx};
+
+        let (mut rows, mut metadata) = typed_rows();
+        let supply =
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        rows[0]["supply"] = json!(supply);
+        rows[1]["supply"] = json!("1");
+        metadata.column_names.push("supply".into());
+        metadata.column_types.push("uint256".into());
+
+        let sheet = |max_rows| {
+            let mut workbook = Workbook::new();
+            write_xlsx_sheet(workbook.add_worksheet(), &rows, &metadata, max_rows).unwrap();
+            let xlsx = workbook.save_to_buffer().unwrap();
+            let mut workbook: Xlsx<_> =
+                calamine::open_workbook_from_rs(io::Cursor::new(xlsx)).unwrap();
+            workbook.worksheet_range("Sheet1").unwrap()
+        };
+
+        let range = sheet(XLSX_MAX_ROWS - 1);
+        let cell = |row, col| range.get_value((row, col)).cloned();
+        assert_eq!(cell(0, 0), Some(Data::String("address".into())));
+        assert_eq!(cell(1, 0), Some(Data::String("0x01".into())));
+        // numbers are number cells, unless too large to be represented exactly
+        assert_eq!(cell(1, 1), Some(Data::Float(1.5)));
+        assert_eq!(cell(2, 1), Some(Data::Empty));
+        assert_eq!(cell(1, 2), Some(Data::Float(3.0)));
+        assert_eq!(cell(1, 3), Some(Data::String(supply.into())));
+        assert_eq!(cell(2, 3), Some(Data::Float(1.0)));
+        assert!(!logs_contain("exceed the Excel limit"));
+
+        // the rows beyond the limit are dropped with a warning
+        assert_eq!(sheet(1).height(), 2);
+        assert!(logs_contain(
+            "2 rows exceed the Excel limit, so only the first 1 are saved"
+        ));
+    }
+
+    #[test]
+    fn test_write_sqlite() {
+        let (rows, metadata) = typed_rows();
+
+        let mut connection = Connection::open_in_memory().unwrap();
+        write_json_to_sqlite(&mut connection, &rows, &metadata, "t", SqliteMode::Create).unwrap();
+        // the table already exists
+        assert!(
+            write_json_to_sqlite(&mut connection, &rows, &metadata, "t", SqliteMode::Create)
+                .is_err()
+        );
+        write_json_to_sqlite(&mut connection, &rows, &metadata, "t", SqliteMode::Append).unwrap();
+
+        let count = |connection: &Connection| -> i64 {
+            connection
+                .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count(&connection), 4);
+        write_json_to_sqlite(&mut connection, &rows, &metadata, "t", SqliteMode::Replace).unwrap();
+        assert_eq!(count(&connection), 2);
+
+        let (address, txs): (Vec<u8>, i64) = connection
+            .query_row(
+                "SELECT address, txs FROM t WHERE balance IS NULL",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((address, txs), (vec![0x02], 4));
+    }
+
+    #[test]
+    fn test_write_gzipped_csv() {
+        let records = vec![json!({ "a": 1, "b": "x" }), json!({ "a": 2, "b": "y" })];
+        let options = CsvOptions {
+            gzip: true,
+            ..Default::default()
+        };
+
+        let mut compressed = Vec::new();
+        write_csv_target(&records, None, &mut compressed, &options).unwrap();
+
+        let mut csv = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(csv, "a;b\n1;x\n2;y\n");
+    }
+}